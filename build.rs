@@ -1,6 +1,6 @@
 //! Buildscript which will save a `rust_g.dm` with the DLL's public API.
 
-use std::{fs::File, io::Write};
+use std::{fs::File, io::Write, path::Path};
 
 macro_rules! feature_dm_file {
     ($name:expr) => {
@@ -25,6 +25,7 @@ fn main() {
     )
     .unwrap();
 
+    let mut enabled_features = Vec::new();
     for (key, _value) in std::env::vars() {
         // CARGO_FEATURE_<name> — For each activated feature of the package being built, this environment variable will be present where <name> is the name of the feature uppercased and having - translated to _.
         if let Some(uprfeature) = key.strip_prefix("CARGO_FEATURE_") {
@@ -37,6 +38,132 @@ fn main() {
                 )
                 .unwrap();
             }
+            enabled_features.push(uprfeature.to_lowercase());
         }
     }
+
+    write_capabilities(enabled_features);
+}
+
+/// Saves the tables behind the `features` export: which features are enabled,
+/// and every `byond_fn!` export compiled in along with its argument names.
+fn write_capabilities(mut enabled_features: Vec<String>) {
+    let declared = declared_features();
+    enabled_features.sort();
+
+    let mut exports = Vec::new();
+    for module in compiled_modules(&enabled_features) {
+        let source = std::fs::read_to_string(format!("src/{module}.rs"))
+            .or_else(|_| std::fs::read_to_string(format!("src/{module}/mod.rs")));
+        if let Ok(source) = source {
+            exports.extend(parse_exports(&source, &enabled_features));
+        }
+    }
+    exports.sort();
+
+    // Optional dependencies show up as features too, only list the real ones.
+    enabled_features.retain(|feature| declared.contains(feature));
+
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    let mut f = File::create(Path::new(&out_dir).join("capabilities.rs")).unwrap();
+    writeln!(f, "pub const FEATURES: &[&str] = &{enabled_features:?};").unwrap();
+    writeln!(f, "pub const EXPORTS: &[(&str, &[&str])] = &[").unwrap();
+    for (name, args) in exports {
+        writeln!(f, "    ({name:?}, &{args:?}),").unwrap();
+    }
+    writeln!(f, "];").unwrap();
+    writeln!(
+        f,
+        "pub const PROFILE: &str = {:?};",
+        std::env::var("PROFILE").unwrap()
+    )
+    .unwrap();
+}
+
+/// Names of the features in the `[features]` table of our Cargo.toml.
+fn declared_features() -> Vec<String> {
+    let manifest = std::fs::read_to_string("Cargo.toml").unwrap();
+    manifest
+        .lines()
+        .skip_while(|line| line.trim() != "[features]")
+        .skip(1)
+        .take_while(|line| !line.starts_with('['))
+        .filter_map(|line| line.split_once(" = "))
+        .map(|(name, _)| name.to_owned())
+        .filter(|name| !name.starts_with([' ', '#']))
+        .collect()
+}
+
+/// Names of the modules declared in lib.rs, skipping those behind a
+/// `#[cfg(feature = ...)]` which isn't enabled.
+fn compiled_modules(enabled_features: &[String]) -> Vec<String> {
+    let lib = std::fs::read_to_string("src/lib.rs").unwrap();
+    let mut modules = Vec::new();
+    let mut required_feature = None;
+    for line in lib.lines().map(str::trim) {
+        if let Some(feature) = line
+            .strip_prefix("#[cfg(feature = \"")
+            .and_then(|line| line.strip_suffix("\")]"))
+        {
+            required_feature = Some(feature);
+            continue;
+        }
+        if line.starts_with("#[") {
+            continue;
+        }
+        let module = line
+            .strip_prefix("pub ")
+            .unwrap_or(line)
+            .strip_prefix("mod ")
+            .and_then(|line| line.strip_suffix(';'));
+        if let Some(module) = module
+            && required_feature
+                .is_none_or(|feature| enabled_features.iter().any(|enabled| enabled == feature))
+        {
+            modules.push(module.to_owned());
+        }
+        required_feature = None;
+    }
+    modules
+}
+
+/// Finds every `byond_fn!(fn name(args) ...)` in a source file, skipping those
+/// behind a `#[cfg(feature = ...)]` which isn't enabled.
+fn parse_exports(source: &str, enabled_features: &[String]) -> Vec<(String, Vec<String>)> {
+    let chunks: Vec<&str> = source.split("byond_fn!(").collect();
+    chunks
+        .windows(2)
+        .filter_map(|pair| {
+            let (before, rest) = (pair[0].trim_end(), pair[1]);
+            if let Some(cfg) = before.lines().last().and_then(|line| {
+                line.trim()
+                    .strip_prefix("#[cfg(feature = \"")?
+                    .strip_suffix("\")]")
+            }) && !enabled_features.iter().any(|feature| feature == cfg)
+            {
+                return None;
+            }
+            let signature = rest.split_once('{')?.0.trim();
+            let (binary, signature) = match signature.strip_prefix("binary ") {
+                Some(signature) => (true, signature),
+                None => (false, signature),
+            };
+            let (name, args) = signature.strip_prefix("fn ")?.split_once('(')?;
+            let args = args.trim_end().strip_suffix(')')?;
+            // Binary exports may name their encoding argument after a `;`,
+            // it is added below either way.
+            let args = args.split_once(';').map_or(args, |(args, _)| args);
+            let mut args: Vec<String> = args
+                .split(',')
+                .map(str::trim)
+                .filter(|arg| !arg.is_empty())
+                // Variadic `...rest` arguments are just named `rest`.
+                .map(|arg| arg.trim_start_matches("...").to_owned())
+                .collect();
+            if binary {
+                args.push("encoding".to_owned());
+            }
+            Some((name.trim().to_owned(), args))
+        })
+        .collect()
 }
@@ -1,3 +1,4 @@
+use crate::byond::{deserialize_byond_bool, report_failure};
 use aho_corasick::{AhoCorasick, AhoCorasickBuilder, MatchKind, StartKind};
 use serde::Deserialize;
 use std::{cell::RefCell, collections::hash_map::HashMap};
@@ -32,16 +33,6 @@ impl AhoCorasickOptions {
     }
 }
 
-fn deserialize_byond_bool<'de, D>(deserializer: D) -> Result<bool, D::Error>
-where
-    D: serde::de::Deserializer<'de>,
-{
-    match u8::deserialize(deserializer)? {
-        0 => Ok(false),
-        _ => Ok(true),
-    }
-}
-
 fn deserialize_matchkind<'de, D>(deserializer: D) -> Result<MatchKind, D::Error>
 where
     D: serde::de::Deserializer<'de>,
@@ -58,8 +49,8 @@ thread_local! {
 }
 
 byond_fn!(fn setup_acreplace(key, patterns_json, replacements_json) {
-    let patterns: Vec<String> = serde_json::from_str(patterns_json).ok()?;
-    let replacements: Vec<String> = serde_json::from_str(replacements_json).ok()?;
+    let patterns: Vec<String> = parse_json(patterns_json)?;
+    let replacements: Vec<String> = parse_json(replacements_json)?;
     let ac = AhoCorasickBuilder::new().build(patterns).unwrap(); // Recommends to just unwrap in the docs
     CREPLACE_MAP.with(|cell| {
         let mut map = cell.borrow_mut();
@@ -69,9 +60,9 @@ byond_fn!(fn setup_acreplace(key, patterns_json, replacements_json) {
 });
 
 byond_fn!(fn setup_acreplace_with_options(key, options_json, patterns_json, replacements_json) {
-    let options: AhoCorasickOptions = serde_json::from_str(options_json).ok()?;
-    let patterns: Vec<String> = serde_json::from_str(patterns_json).ok()?;
-    let replacements: Vec<String> = serde_json::from_str(replacements_json).ok()?;
+    let options: AhoCorasickOptions = parse_json(options_json)?;
+    let patterns: Vec<String> = parse_json(patterns_json)?;
+    let replacements: Vec<String> = parse_json(replacements_json)?;
     let ac = options.auto_configure_and_build(&patterns);
     CREPLACE_MAP.with(|cell| {
         let mut map = cell.borrow_mut();
@@ -83,16 +74,33 @@ byond_fn!(fn setup_acreplace_with_options(key, options_json, patterns_json, repl
 byond_fn!(fn acreplace(key, text) {
     CREPLACE_MAP.with(|cell| -> Option<String> {
         let map = cell.borrow_mut();
-        let replacements = map.get(key)?;
+        let replacements = get_replacements(&map, key)?;
         Some(replacements.automaton.replace_all(text, &replacements.replacements))
     })
 });
 
 byond_fn!(fn acreplace_with_replacements(key, text, replacements_json) {
-    let call_replacements: Vec<String> = serde_json::from_str(replacements_json).ok()?;
+    let call_replacements: Vec<String> = parse_json(replacements_json)?;
     CREPLACE_MAP.with(|cell| -> Option<String> {
         let map = cell.borrow_mut();
-        let replacements = map.get(key)?;
+        let replacements = get_replacements(&map, key)?;
         Some(replacements.automaton.replace_all(text, &call_replacements))
     })
 });
+
+fn parse_json<'a, T: Deserialize<'a>>(json: &'a str) -> Option<T> {
+    serde_json::from_str(json)
+        .inspect_err(|error| report_failure("json", error))
+        .ok()
+}
+
+fn get_replacements<'a>(
+    map: &'a HashMap<String, Replacements>,
+    key: &str,
+) -> Option<&'a Replacements> {
+    let replacements = map.get(key);
+    if replacements.is_none() {
+        report_failure("acreplace", format!("No replacements set up for {key}"));
+    }
+    replacements
+}
@@ -1,20 +1,65 @@
 use crate::error::Error;
+use base64::{Engine, prelude::BASE64_STANDARD};
+use chrono::{SecondsFormat, Utc};
+use serde_json::{Value, json};
 use std::{
     backtrace::Backtrace,
     borrow::Cow,
-    cell::RefCell,
+    cell::{Cell, RefCell},
     ffi::{CStr, CString},
-    fs::OpenOptions,
-    io::Write,
+    fmt::Display,
+    fs::{self, OpenOptions},
+    io::{self, Write},
     os::raw::{c_char, c_int},
     slice,
-    sync::Once,
+    sync::{Mutex, Once, PoisonError},
 };
 
+/// Encoding name accepted as the extra trailing argument of `binary` exports.
+pub const BINARY_BASE64: &str = "base64";
+
+const PANIC_LOG: &str = "rustg-panic.log";
+/// Once the panic log reaches this size it is rotated to `rustg-panic.log.1`.
+const PANIC_LOG_MAX_BYTES: u64 = 8 * 1024 * 1024;
+/// How many rotated panic logs are kept around.
+const PANIC_LOG_KEEP: usize = 3;
+/// Arguments longer than this are cut short in the panic log.
+const PANIC_ARG_MAX_BYTES: usize = 256;
+/// Exports whose arguments can't hold anything sensitive, so the panic log
+/// records them as given. For every other export only the length of each
+/// argument is logged, as they include database passwords, HTTP auth headers
+/// and the like. Add an export here to opt it in.
+const PANIC_LOGGED_ARGS: &[&str] = &[
+    "cave_system_generator_generate",
+    "cnoise_generate",
+    "dbp_generate",
+    "generate_path_astar",
+    "noise_get_at_coordinates",
+    "noise_poisson_map",
+    "roll_dice",
+    "worley_generate",
+];
+
 static SET_HOOK: Once = Once::new();
+static PANIC_LOG_LOCK: Mutex<()> = Mutex::new(());
 static EMPTY_STRING: c_char = 0;
 thread_local! {
     static RETURN_STRING: RefCell<CString> = RefCell::new(CString::default());
+    static RESULT_ENVELOPE: Cell<bool> = const { Cell::new(false) };
+    static EXPORT_FAILURE: RefCell<Option<Failure>> = const { RefCell::new(None) };
+    static CURRENT_EXPORT: RefCell<Option<ExportContext>> = const { RefCell::new(None) };
+}
+
+/// The export running on this thread, for the panic log.
+struct ExportContext {
+    name: &'static str,
+    args: Vec<String>,
+}
+
+/// An error reported by the running export, kept for the result envelope.
+struct Failure {
+    kind: &'static str,
+    message: String,
 }
 
 pub unsafe fn parse_args<'a>(argc: c_int, argv: *const *const c_char) -> Vec<Cow<'a, str>> {
@@ -51,6 +96,138 @@ pub fn byond_return(value: Option<Vec<u8>>) -> *const c_char {
     }
 }
 
+/// Like `byond_return`, but if `encoding` is `BINARY_BASE64` the value is
+/// base64 encoded instead of being cut off at its first NUL byte.
+pub fn byond_return_binary(value: Option<Vec<u8>>, encoding: &str) -> *const c_char {
+    byond_return(value.map(|bytes| encode_binary(bytes, encoding)))
+}
+
+fn encode_binary(bytes: Vec<u8>, encoding: &str) -> Vec<u8> {
+    match encoding {
+        BINARY_BASE64 => BASE64_STANDARD.encode(bytes).into_bytes(),
+        _ => bytes,
+    }
+}
+
+/// Reads a DM boolean out of JSON. `json_encode` writes `TRUE` as `1`, but
+/// `true` and `false` are accepted as well.
+pub fn byond_bool(value: &Value) -> Option<bool> {
+    match value {
+        Value::Bool(value) => Some(*value),
+        Value::Number(number) => number.as_f64().map(|number| number != 0.0),
+        _ => None,
+    }
+}
+
+/// `#[serde(deserialize_with)]` version of `byond_bool`.
+#[cfg(feature = "serde")]
+#[allow(dead_code)] // Used depending on feature set
+pub fn deserialize_byond_bool<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: serde::de::Deserializer<'de>,
+{
+    use serde::{Deserialize, de::Error};
+    byond_bool(&Value::deserialize(deserializer)?)
+        .ok_or_else(|| D::Error::custom("expected a boolean or a number"))
+}
+
+/// Notes that the running export failed with `error`. Exports still return
+/// whatever they always have; this only shows up when DM has enabled the
+/// result envelope.
+pub fn report_error(error: &Error) {
+    report_failure(error.kind(), error);
+}
+
+/// Like `report_error`, for failures which aren't an `Error`.
+pub fn report_failure(kind: &'static str, message: impl Display) {
+    EXPORT_FAILURE.with(|failure| {
+        failure.replace(Some(Failure {
+            kind,
+            message: message.to_string(),
+        }))
+    });
+}
+
+/// `{"error": {"kind", "message"}}`, the failure shape of the result envelope.
+/// Exports which return JSON of their own use it for their failures too.
+pub fn failure_json(error: &Error) -> Value {
+    json!({"error": {"kind": error.kind(), "message": error.to_string()}})
+}
+
+/// `{"ok": value}` or `failure_json`, for exports which return JSON whether
+/// or not they succeed.
+#[allow(dead_code)] // Used depending on feature set
+pub fn result_json(result: Result<Value, Error>) -> String {
+    match result {
+        Ok(value) => json!({ "ok": value }),
+        Err(error) => failure_json(&error),
+    }
+    .to_string()
+}
+
+/// Hands the result of an export to BYOND. With the result envelope enabled
+/// this is `{"ok": value}`, or `{"error": {"kind", "message"}}` if the export
+/// panicked or reported a failure.
+pub fn export_return(result: Result<Option<Vec<u8>>, Error>, encoding: &str) -> *const c_char {
+    let failure = EXPORT_FAILURE.with(RefCell::take);
+    if !RESULT_ENVELOPE.with(Cell::get) {
+        return match result {
+            Ok(value) => byond_return_binary(value, encoding),
+            Err(panic) => byond_return(Some(panic.into())),
+        };
+    }
+
+    let envelope = match (result, failure) {
+        (Err(panic), _) => failure_json(&panic),
+        (Ok(_), Some(Failure { kind, message })) => {
+            json!({"error": {"kind": kind, "message": message}})
+        }
+        (Ok(value), None) => json!({
+            "ok": value.map(|bytes| String::from_utf8_lossy(&encode_binary(bytes, encoding)).into_owned())
+        }),
+    };
+    byond_return(Some(envelope.to_string().into_bytes()))
+}
+
+/// Runs the body of an export. `byond_fn!` wraps every export in this so a
+/// panic is logged and returned to DM as an error, instead of unwinding into
+/// BYOND and taking the server down with it.
+pub fn catch_export<F>(
+    name: &'static str,
+    args: &[Cow<str>],
+    f: F,
+) -> Result<Option<Vec<u8>>, Error>
+where
+    F: FnOnce() -> Option<Vec<u8>>,
+{
+    set_panic_hook();
+    EXPORT_FAILURE.with(RefCell::take);
+    CURRENT_EXPORT.with(|current| {
+        current.replace(Some(ExportContext {
+            name,
+            args: args.iter().map(|arg| panic_log_arg(name, arg)).collect(),
+        }))
+    });
+    let result = catch_panic(std::panic::AssertUnwindSafe(f));
+    CURRENT_EXPORT.with(RefCell::take);
+    result
+}
+
+/// How `arg` of the export `name` appears in the panic log.
+fn panic_log_arg(name: &str, arg: &str) -> String {
+    if !PANIC_LOGGED_ARGS.contains(&name) {
+        return format!("({} bytes)", arg.len());
+    }
+    if arg.len() <= PANIC_ARG_MAX_BYTES {
+        return arg.to_owned();
+    }
+    let mut end = PANIC_ARG_MAX_BYTES;
+    while !arg.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}... ({} bytes)", &arg[..end], arg.len())
+}
+
 #[macro_export]
 macro_rules! byond_fn {
     (fn $name:ident() $body:block) => {
@@ -59,9 +236,10 @@ macro_rules! byond_fn {
         pub unsafe extern "C" fn $name(
             _argc: ::std::os::raw::c_int, _argv: *const *const ::std::os::raw::c_char
         ) -> *const ::std::os::raw::c_char {
-            $crate::byond::set_panic_hook();
             let closure = || ($body);
-            $crate::byond::byond_return(closure().map(From::from))
+            $crate::metrics::measure(stringify!($name), 0, || {
+                $crate::byond::export_return($crate::byond::catch_export(stringify!($name), &[], || closure().map(From::from)), "")
+            })
         }
     };
 
@@ -82,8 +260,41 @@ macro_rules! byond_fn {
                 let $rest = __args.get(__argn..).unwrap_or(&[]);
             )?
 
+            let __arg_bytes = __args.iter().map(|arg| arg.len()).sum();
+            let closure = || ($body);
+            $crate::metrics::measure(stringify!($name), __arg_bytes, || {
+                $crate::byond::export_return($crate::byond::catch_export(stringify!($name), &__args, || closure().map(From::from)), "")
+            })
+        }
+    };
+
+    // Binary-safe exports take one extra optional argument, the encoding to
+    // return their result in. Without it they behave like any other export.
+    // Naming it after a `;` lets the body see it too, for exports which
+    // only treat their result as text when it isn't encoded.
+    (binary fn $name:ident($($arg:ident),* $(; $encoding:ident)?) $body:block) => {
+        #[unsafe(no_mangle)]
+        #[allow(clippy::missing_safety_doc)]
+        pub unsafe extern "C" fn $name(
+            _argc: ::std::os::raw::c_int, _argv: *const *const ::std::os::raw::c_char
+        ) -> *const ::std::os::raw::c_char {
+            let __args = unsafe { $crate::byond::parse_args(_argc, _argv) };
+
+            let mut __argn = 0;
+            $(
+                let $arg: &str = __args.get(__argn).map_or("", |cow| &*cow);
+                __argn += 1;
+            )*
+            let __encoding: &str = __args.get(__argn).map_or("", |cow| &*cow);
+            $(
+                let $encoding = __encoding;
+            )?
+
+            let __arg_bytes = __args.iter().map(|arg| arg.len()).sum();
             let closure = || ($body);
-            $crate::byond::byond_return(closure().map(From::from))
+            $crate::metrics::measure(stringify!($name), __arg_bytes, || {
+                $crate::byond::export_return($crate::byond::catch_export(stringify!($name), &__args, || closure().map(From::from)), __encoding)
+            })
         }
     };
 }
@@ -95,56 +306,91 @@ byond_fn!(
     }
 );
 
-/// Print any panics before exiting.
+/// Generated by build.rs from the same feature list it assembles `rust_g.dm` from.
+mod capabilities {
+    include!(concat!(env!("OUT_DIR"), "/capabilities.rs"));
+}
+
+// Lets DM check what this build of rust_g supports before calling into it.
+byond_fn!(
+    fn features() {
+        let exports: serde_json::Map<String, serde_json::Value> = capabilities::EXPORTS
+            .iter()
+            .map(|(name, args)| (name.to_string(), json!(args)))
+            .collect();
+        Some(
+            json!({
+                "version": env!("CARGO_PKG_VERSION"),
+                "features": capabilities::FEATURES,
+                "exports": exports,
+                "profile": capabilities::PROFILE,
+            })
+            .to_string(),
+        )
+    }
+);
+
+byond_fn!(fn set_result_envelope(enabled) {
+    RESULT_ENVELOPE.with(|envelope| envelope.set(enabled == "1"));
+    Some("")
+});
+
+/// Print any panics before exiting, and record them in `rustg-panic.log` as
+/// one JSON object per line.
 pub fn set_panic_hook() {
     SET_HOOK.call_once(|| {
         let default_panic_handler = std::panic::take_hook();
         std::panic::set_hook(Box::new(move |panic_info| {
             default_panic_handler(panic_info);
-            let mut file = match OpenOptions::new()
-                .append(true)
-                .create(true)
-                .open("rustg-panic.log")
-            {
-                Ok(file) => file,
-                Err(res) => {
-                    eprintln!("panic_hook: Cannot open file: {res:?}");
-                    return;
-                }
-            };
 
-            let payload = match panic_info
+            let message = panic_info
                 .payload()
                 .downcast_ref::<&'static str>()
                 .map(|payload| payload.to_string())
-                .or_else(|| panic_info.payload().downcast_ref::<String>().cloned())
-            {
-                Some(pl) => pl,
-                None => {
-                    eprintln!("panic_hook: Failed to extract panic payload");
-                    return;
-                }
-            };
-
-            match file.write_all(payload.as_bytes()) {
-                Ok(_) => {}
-                Err(err) => {
-                    eprintln!("panic_hook: Failed to write error payload: {err:?}");
-                    return;
-                }
-            };
+                .or_else(|| panic_info.payload().downcast_ref::<String>().cloned());
+            let (export, args) = CURRENT_EXPORT
+                .try_with(|current| match &*current.borrow() {
+                    Some(context) => (Some(context.name), context.args.clone()),
+                    None => (None, Vec::new()),
+                })
+                .unwrap_or_default();
+            let thread = std::thread::current();
 
-            match file.write_all(Backtrace::capture().to_string().as_bytes()) {
-                Ok(_) => {}
-                Err(err) => {
-                    eprintln!("panic_hook: Failed to extract backtrace: {err:?}");
-                }
-            };
+            let entry = json!({
+                "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+                "export": export,
+                "args": args,
+                "thread": thread.name().map_or_else(|| format!("{:?}", thread.id()), String::from),
+                "message": message,
+                "location": panic_info.location().map(ToString::to_string),
+                "backtrace": Backtrace::capture().to_string(),
+            });
+            if let Err(err) = write_panic_log(&entry) {
+                eprintln!("panic_hook: Failed to write panic log: {err:?}");
+            }
         }))
     });
 }
 
-#[allow(dead_code)] // Used depending on feature set
+fn write_panic_log(entry: &Value) -> io::Result<()> {
+    let _guard = PANIC_LOG_LOCK
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    if fs::metadata(PANIC_LOG).is_ok_and(|metadata| metadata.len() >= PANIC_LOG_MAX_BYTES) {
+        for n in (1..PANIC_LOG_KEEP).rev() {
+            // Older logs may not exist yet.
+            let _ = fs::rename(format!("{PANIC_LOG}.{n}"), format!("{PANIC_LOG}.{}", n + 1));
+        }
+        fs::rename(PANIC_LOG, format!("{PANIC_LOG}.1"))?;
+    }
+
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(PANIC_LOG)?;
+    writeln!(file, "{entry}")
+}
+
 /// Utility for BYOND functions to catch panic unwinds safely and return a Result<String, Error>, as expected.
 /// Usage: catch_panic(|| internal_safe_function(arguments))
 pub fn catch_panic<F, R>(f: F) -> Result<R, Error>
@@ -168,3 +414,22 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panic_log_only_keeps_arguments_of_opted_in_exports() {
+        assert_eq!(
+            panic_log_arg("sql_connect_pool", r#"{"pass": "hunter2"}"#),
+            "(19 bytes)"
+        );
+        assert_eq!(panic_log_arg("roll_dice", "2d6"), "2d6");
+        let long = "é".repeat(PANIC_ARG_MAX_BYTES);
+        assert_eq!(
+            panic_log_arg("roll_dice", &long),
+            format!("{}... (512 bytes)", "é".repeat(PANIC_ARG_MAX_BYTES / 2))
+        );
+    }
+}
@@ -1,4 +1,7 @@
-use crate::error::Result;
+use crate::{
+    byond::{deserialize_byond_bool, report_error},
+    error::Result,
+};
 use rand::Rng;
 use rand::distr::{Bernoulli, Distribution, Uniform};
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
@@ -34,7 +37,7 @@ byond_fn!(fn cave_system_generator_generate(
         survival_limit: survival_limit.parse::<usize>().unwrap_or(4),
         edge_is_alive:  edge_is_alive.parse::<u8>().unwrap_or(0) != 0,
     };
-    generate_cave_system(width, height, prefabs_json, config).ok()
+    generate_cave_system(width, height, prefabs_json, config).inspect_err(report_error).ok()
 });
 
 // ─── Cell States ───────────────────────────────────────────────────────────────
@@ -62,13 +65,6 @@ struct GeneratorConfig {
 
 // ─── Input Structs ─────────────────────────────────────────────────────────────
 
-fn deserialize_byond_bool<'de, D>(deserializer: D) -> std::result::Result<bool, D::Error>
-where
-    D: serde::de::Deserializer<'de>,
-{
-    u8::deserialize(deserializer).map(|x| x != 0)
-}
-
 #[derive(Deserialize)]
 struct PrefabConfig {
     x: usize,
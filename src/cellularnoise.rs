@@ -1,9 +1,9 @@
-use crate::error::Result;
+use crate::{byond::report_error, error::Result};
 use rand::distr::{Bernoulli, Distribution};
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 
 byond_fn!(fn cnoise_generate(percentage, smoothing_iterations, birth_limit, death_limit, width, height) {
-    noise_gen(percentage, smoothing_iterations, birth_limit, death_limit, width, height).ok()
+    noise_gen(percentage, smoothing_iterations, birth_limit, death_limit, width, height).inspect_err(report_error).ok()
 });
 
 fn noise_gen(
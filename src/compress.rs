@@ -0,0 +1,300 @@
+use crate::{
+    byond::{report_error, result_json},
+    error::{Error, Result},
+    jobs::{self, CancellableReader, JobKind, ProgressReader},
+    sandbox::check_path,
+};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use flate2::{Compression, read::MultiGzDecoder, write::GzEncoder};
+use serde_json::{Value, json};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+/// How much `decompress_string` will inflate to unless told otherwise, so a
+/// small payload can't exhaust memory.
+const DEFAULT_MAX_STRING_OUTPUT: u64 = 64 * 1024 * 1024;
+/// Like `DEFAULT_MAX_STRING_OUTPUT`, for the files written by `decompress_file`.
+const DEFAULT_MAX_FILE_OUTPUT: u64 = 4 * 1024 * 1024 * 1024;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Clone, Copy)]
+enum Format {
+    Gzip,
+    Zstd,
+}
+
+impl Format {
+    fn parse(format: &str) -> Result<Self> {
+        match format {
+            "gzip" | "gz" => Ok(Self::Gzip),
+            "zstd" | "zst" => Ok(Self::Zstd),
+            _ => Err(Error::UnknownCompression(format.to_owned())),
+        }
+    }
+
+    /// Like `parse`, but an empty format or "auto" is detected from the magic
+    /// bytes at the start of `data`.
+    fn parse_or_detect(format: &str, data: &[u8]) -> Result<Self> {
+        match format {
+            "" | "auto" if data.starts_with(GZIP_MAGIC) => Ok(Self::Gzip),
+            "" | "auto" if data.starts_with(ZSTD_MAGIC) => Ok(Self::Zstd),
+            "" | "auto" => Err(Error::UnrecognizedCompression),
+            _ => Self::parse(format),
+        }
+    }
+
+    /// Checks `level` against what the format supports. An empty level picks
+    /// the format's default.
+    fn level(self, level: &str) -> Result<i32> {
+        if level.is_empty() {
+            return Ok(match self {
+                Self::Gzip => Compression::default().level() as i32,
+                Self::Zstd => zstd::DEFAULT_COMPRESSION_LEVEL,
+            });
+        }
+        let level: i32 = level.parse()?;
+        let valid = match self {
+            Self::Gzip => 0..=9,
+            Self::Zstd => zstd::compression_level_range(),
+        };
+        if valid.contains(&level) {
+            Ok(level)
+        } else {
+            Err(Error::InvalidCompressionLevel(level))
+        }
+    }
+}
+
+byond_fn!(fn compress_string(format, level, data) {
+    compress_string_impl(format, level, data.as_bytes())
+        .map(|bytes| BASE64_STANDARD.encode(bytes))
+        .inspect_err(report_error)
+        .ok()
+});
+
+byond_fn!(binary fn decompress_string(format, data, max_output) {
+    BASE64_STANDARD
+        .decode(data)
+        .map_err(From::from)
+        .and_then(|bytes| decompress(format, &bytes, max_output_or(max_output, DEFAULT_MAX_STRING_OUTPUT)?))
+        .inspect_err(report_error)
+        .ok()
+});
+
+byond_fn!(fn compress_file(format, level, from, to) {
+    let (format, level, from, to) = (format.to_owned(), level.to_owned(), from.to_owned(), to.to_owned());
+    Some(jobs::start(JobKind::Compress, move || {
+        result_json(compress_file_impl(&format, &level, &from, &to).map(sizes_json))
+    }))
+});
+
+byond_fn!(fn decompress_file(format, from, to, max_output) {
+    let (format, from, to, max_output) = (format.to_owned(), from.to_owned(), to.to_owned(), max_output.to_owned());
+    Some(jobs::start(JobKind::Compress, move || {
+        result_json(
+            max_output_or(&max_output, DEFAULT_MAX_FILE_OUTPUT)
+                .and_then(|max_output| decompress_file_impl(&format, &from, &to, max_output))
+                .map(sizes_json),
+        )
+    }))
+});
+
+byond_fn!(fn compress_check(id) {
+    Some(jobs::check(id))
+});
+
+fn sizes_json((read, written): (u64, u64)) -> Value {
+    json!({"bytes_in": read, "bytes_out": written})
+}
+
+fn compress_string_impl(format: &str, level: &str, data: &[u8]) -> Result<Vec<u8>> {
+    let format = Format::parse(format)?;
+    let level = format.level(level)?;
+    let mut output = Vec::new();
+    compress(format, level, data, &mut output)?;
+    Ok(output)
+}
+
+/// Parses a `max_output` argument, an empty one being `default`.
+fn max_output_or(max_output: &str, default: u64) -> Result<u64> {
+    if max_output.is_empty() {
+        Ok(default)
+    } else {
+        Ok(max_output.parse()?)
+    }
+}
+
+fn decompress(format: &str, data: &[u8], max_output: u64) -> Result<Vec<u8>> {
+    let format = Format::parse_or_detect(format, data)?;
+    let mut output = Vec::new();
+    decoder(format, data)?
+        .take(max_output.saturating_add(1))
+        .read_to_end(&mut output)?;
+    if output.len() as u64 > max_output {
+        return Err(Error::DecompressedTooLarge(max_output));
+    }
+    Ok(output)
+}
+
+/// Streams `input` into `output`, returning how many bytes were read.
+fn compress(format: Format, level: i32, mut input: impl Read, output: impl Write) -> Result<u64> {
+    let read = match format {
+        Format::Gzip => {
+            let mut encoder = GzEncoder::new(output, Compression::new(level as u32));
+            let read = std::io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?;
+            read
+        }
+        Format::Zstd => {
+            let mut encoder = zstd::Encoder::new(output, level)?;
+            let read = std::io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?;
+            read
+        }
+    };
+    Ok(read)
+}
+
+fn decoder<'a>(format: Format, input: impl Read + 'a) -> Result<Box<dyn Read + 'a>> {
+    Ok(match format {
+        Format::Gzip => Box::new(MultiGzDecoder::new(input)),
+        Format::Zstd => Box::new(zstd::Decoder::new(input)?),
+    })
+}
+
+fn compress_file_impl(format: &str, level: &str, from: &str, to: &str) -> Result<(u64, u64)> {
+    let format = Format::parse(format)?;
+    let level = format.level(level)?;
+    check_distinct(from, to)?;
+    let (input, _) = open_input(from)?;
+    write_output(to, |output| compress(format, level, input, output))
+}
+
+fn decompress_file_impl(format: &str, from: &str, to: &str, max_output: u64) -> Result<(u64, u64)> {
+    check_distinct(from, to)?;
+    let (input, len) = open_input(from)?;
+    let mut input = BufReader::new(input);
+    let format = Format::parse_or_detect(format, std::io::BufRead::fill_buf(&mut input)?)?;
+    let mut decoder = decoder(format, input)?.take(max_output.saturating_add(1));
+    write_output(to, |output| {
+        if std::io::copy(&mut decoder, output)? > max_output {
+            return Err(Error::DecompressedTooLarge(max_output));
+        }
+        Ok(len)
+    })
+}
+
+/// Creating `to` truncates it, so it must not be the file being read.
+fn check_distinct(from: &str, to: &str) -> Result<()> {
+    let same = match (std::fs::canonicalize(from), std::fs::canonicalize(to)) {
+        (Ok(from), Ok(to)) => from == to,
+        _ => false,
+    };
+    if same {
+        return Err(Error::CompressInPlace(to.to_owned()));
+    }
+    Ok(())
+}
+
+/// Opens `path` for reading as part of the running job, so progress counts
+/// the input's bytes and cancelling the job stops the read. Also returns the
+/// file's size.
+fn open_input(path: &str) -> Result<(impl Read + use<>, u64)> {
+    check_path(path)?;
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let progress = jobs::progress();
+    progress.begin("bytes", Some(len));
+    let reader = ProgressReader::new(CancellableReader::new(file, jobs::current()), progress);
+    Ok((reader, len))
+}
+
+/// Runs `f` against a fresh file at `path`, returning what `f` returned along
+/// with the file's final size. A half-written file is removed on failure.
+fn write_output(path: &str, f: impl FnOnce(&mut dyn Write) -> Result<u64>) -> Result<(u64, u64)> {
+    check_path(path)?;
+    if let Some(parent) = Path::new(path).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut output = BufWriter::new(File::create(path)?);
+    let result = f(&mut output).and_then(|read| {
+        let file = output.into_inner().map_err(|error| error.into_error())?;
+        Ok((read, file.metadata()?.len()))
+    });
+    if result.is_err() {
+        let _ = std::fs::remove_file(path);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_and_detects_format() {
+        let text = "round end ".repeat(64);
+        for format in ["gzip", "zstd"] {
+            let compressed = compress_string_impl(format, "", text.as_bytes()).unwrap();
+            assert!(compressed.len() < text.len());
+            assert_eq!(
+                decompress("", &compressed, u64::MAX).unwrap(),
+                text.as_bytes()
+            );
+            assert_eq!(
+                decompress(format, &compressed, u64::MAX).unwrap(),
+                text.as_bytes()
+            );
+        }
+        assert!(compress_string_impl("gzip", "10", b"").is_err());
+        assert!(decompress("", b"not compressed", u64::MAX).is_err());
+    }
+
+    #[test]
+    fn decompression_stops_at_the_output_limit() {
+        let bomb = compress_string_impl("zstd", "", &[0; 1024 * 1024]).unwrap();
+        assert!(bomb.len() < 1024);
+        assert_eq!(
+            decompress("", &bomb, 1024 * 1024).unwrap().len(),
+            1024 * 1024
+        );
+        let error = decompress("", &bomb, 1024 * 1024 - 1).unwrap_err();
+        assert!(matches!(error, Error::DecompressedTooLarge(_)));
+
+        let dir = std::env::temp_dir().join(format!("rustg-compress-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let from = dir.join("bomb.zst");
+        let to = dir.join("bomb");
+        std::fs::write(&from, &bomb).unwrap();
+        let (from, to) = (from.to_str().unwrap(), to.to_str().unwrap());
+        assert!(decompress_file_impl("", from, to, 1024).is_err());
+        assert!(!Path::new(to).exists());
+        assert_eq!(
+            decompress_file_impl("", from, to, u64::MAX).unwrap().1,
+            1024 * 1024
+        );
+    }
+
+    #[test]
+    fn files_cannot_be_written_over_themselves() {
+        let dir = std::env::temp_dir().join(format!("rustg-compress-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("in_place.txt");
+        std::fs::write(&path, "contents").unwrap();
+        let path = path.to_str().unwrap();
+        assert!(matches!(
+            compress_file_impl("gzip", "", path, path),
+            Err(Error::CompressInPlace(_))
+        ));
+        assert!(matches!(
+            decompress_file_impl("", path, path, u64::MAX),
+            Err(Error::CompressInPlace(_))
+        ));
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "contents");
+    }
+}
@@ -0,0 +1,251 @@
+use crate::{
+    byond::{deserialize_byond_bool, report_error, result_json},
+    error::{Error, Result},
+    sandbox::check_path,
+};
+use csv_dep::{ReaderBuilder, StringRecord, Terminator, WriterBuilder};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+byond_fn!(fn csv_to_json(path_or_text, options) {
+    Some(result_json(csv_to_json_impl(path_or_text, options).inspect_err(report_error)))
+});
+
+byond_fn!(fn json_to_csv(value, path, options) {
+    Some(result_json(json_to_csv_impl(value, path, options).map(Value::String).inspect_err(report_error)))
+});
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct CsvOptions {
+    /// Whether the first row names the columns.
+    #[serde(deserialize_with = "deserialize_byond_bool")]
+    headers: bool,
+    delimiter: char,
+    quote: char,
+    /// Read `path_or_text` as a path instead of as the CSV itself.
+    #[serde(deserialize_with = "deserialize_byond_bool")]
+    file: bool,
+    /// Allow rows with differing numbers of fields.
+    #[serde(deserialize_with = "deserialize_byond_bool")]
+    flexible: bool,
+    #[serde(deserialize_with = "deserialize_byond_bool")]
+    trim: bool,
+    /// Write CRLF line endings instead of LF.
+    #[serde(deserialize_with = "deserialize_byond_bool")]
+    crlf: bool,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self {
+            headers: true,
+            delimiter: ',',
+            quote: '"',
+            file: false,
+            flexible: false,
+            trim: false,
+            crlf: false,
+        }
+    }
+}
+
+impl CsvOptions {
+    fn parse(options: &str) -> Result<Self> {
+        if options.is_empty() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_str(options)?)
+    }
+
+    fn delimiter(&self) -> Result<u8> {
+        ascii_byte(self.delimiter, "delimiter")
+    }
+
+    fn quote(&self) -> Result<u8> {
+        ascii_byte(self.quote, "quote")
+    }
+}
+
+fn ascii_byte(c: char, what: &str) -> Result<u8> {
+    if c.is_ascii() {
+        Ok(c as u8)
+    } else {
+        Err(Error::InvalidCsv(format!(
+            "The {what} must be an ASCII character."
+        )))
+    }
+}
+
+/// Parses CSV into a list of objects keyed by the header row, or with
+/// `headers` off, a list of lists. Every field is kept as a string.
+fn csv_to_json_impl(path_or_text: &str, options: &str) -> Result<Value> {
+    let options = CsvOptions::parse(options)?;
+    let mut builder = ReaderBuilder::new();
+    builder
+        .has_headers(options.headers)
+        .delimiter(options.delimiter()?)
+        .quote(options.quote()?)
+        .flexible(options.flexible);
+    if options.trim {
+        builder.trim(csv_dep::Trim::All);
+    }
+    let input: Box<dyn std::io::Read> = if options.file {
+        check_path(path_or_text)?;
+        Box::new(std::fs::File::open(path_or_text)?)
+    } else {
+        Box::new(path_or_text.as_bytes())
+    };
+    let mut reader = builder.from_reader(input);
+
+    let headers = if options.headers {
+        let headers = reader.headers()?.clone();
+        if let Some((i, name)) = headers
+            .iter()
+            .enumerate()
+            .find(|(i, name)| headers.iter().take(*i).any(|earlier| earlier == *name))
+        {
+            return Err(Error::InvalidCsv(format!(
+                "Column {} repeats the column name \"{name}\".",
+                i + 1
+            )));
+        }
+        Some(headers)
+    } else {
+        None
+    };
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        rows.push(match &headers {
+            Some(headers) => Value::Object(record_to_object(headers, &record)),
+            None => record.iter().collect(),
+        });
+    }
+    Ok(Value::Array(rows))
+}
+
+fn record_to_object(headers: &StringRecord, record: &StringRecord) -> Map<String, Value> {
+    record
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            // Extra fields in flexible mode get their position as a name.
+            let name = headers.get(i).map_or_else(|| i.to_string(), String::from);
+            (name, Value::from(field))
+        })
+        .collect()
+}
+
+/// Turns a list of objects or a list of lists into CSV. For objects the header
+/// row is every key seen, in the order first seen. Returns the CSV, or writes
+/// it to `path` and returns nothing if a path is given.
+fn json_to_csv_impl(value: &str, path: &str, options: &str) -> Result<String> {
+    let options = CsvOptions::parse(options)?;
+    let Value::Array(rows) = serde_json::from_str(value)? else {
+        return Err(Error::InvalidCsv("Expected a list of rows.".to_owned()));
+    };
+
+    let mut columns: Vec<&str> = Vec::new();
+    for row in &rows {
+        match row {
+            Value::Object(object) => {
+                for key in object.keys() {
+                    if !columns.contains(&key.as_str()) {
+                        columns.push(key);
+                    }
+                }
+            }
+            Value::Array(_) => {}
+            _ => {
+                return Err(Error::InvalidCsv(
+                    "Every row must be a list or an object.".to_owned(),
+                ));
+            }
+        }
+    }
+
+    let mut writer = WriterBuilder::new()
+        .delimiter(options.delimiter()?)
+        .quote(options.quote()?)
+        .flexible(true)
+        .terminator(if options.crlf {
+            Terminator::CRLF
+        } else {
+            Terminator::Any(b'\n')
+        })
+        .from_writer(Vec::new());
+    if options.headers && !columns.is_empty() {
+        writer.write_record(&columns)?;
+    }
+    for row in &rows {
+        let fields: Vec<String> = match row {
+            Value::Object(object) => columns
+                .iter()
+                .map(|column| object.get(*column).map(field_text).unwrap_or_default())
+                .collect(),
+            Value::Array(array) => array.iter().map(field_text).collect(),
+            _ => unreachable!("rows were checked above"),
+        };
+        writer.write_record(&fields)?;
+    }
+    let csv = writer
+        .into_inner()
+        .map_err(|error| std::io::Error::new(error.error().kind(), error.error().to_string()))?;
+    let csv = String::from_utf8(csv).map_err(|error| error.utf8_error())?;
+
+    if path.is_empty() {
+        return Ok(csv);
+    }
+    check_path(path)?;
+    if let Some(parent) = std::path::Path::new(path).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, csv)?;
+    Ok(String::new())
+}
+
+fn field_text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(string) => string.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn round_trips_with_a_custom_delimiter_and_quote() {
+        let options = r#"{"delimiter": ";", "quote": "'"}"#;
+        let rows = json!([
+            {"name": "Urist; McMiner", "quote": "it's", "count": 3},
+            {"name": "plain", "note": "new\nline"},
+        ]);
+        let csv = json_to_csv_impl(&rows.to_string(), "", options).unwrap();
+        assert_eq!(
+            csv,
+            "name;quote;count;note\n'Urist; McMiner';'it''s';3;\nplain;;;'new\nline'\n"
+        );
+
+        let parsed = csv_to_json_impl(&csv, options).unwrap();
+        assert_eq!(
+            parsed,
+            json!([
+                {"name": "Urist; McMiner", "quote": "it's", "count": "3", "note": ""},
+                {"name": "plain", "quote": "", "count": "", "note": "new\nline"},
+            ])
+        );
+    }
+
+    #[test]
+    fn repeated_header_names_are_an_error() {
+        let error = csv_to_json_impl("a,b,a\n1,2,3\n", "").unwrap_err();
+        assert_eq!(error.kind(), "csv");
+        assert!(error.to_string().contains("Column 3"));
+        assert!(csv_to_json_impl("a,b,a\n1,2,3\n", r#"{"headers": 0}"#).is_ok());
+    }
+}
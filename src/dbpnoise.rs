@@ -1,8 +1,8 @@
-use crate::error::Result;
+use crate::{byond::report_error, error::Result};
 use dbpnoise::gen_noise;
 
 byond_fn!(fn dbp_generate(seed, accuracy, stamp_size, world_size, lower_range, upper_range) {
-    gen_dbp_noise(seed, accuracy, stamp_size, world_size, lower_range, upper_range).ok()
+    gen_dbp_noise(seed, accuracy, stamp_size, world_size, lower_range, upper_range).inspect_err(report_error).ok()
 });
 
 fn gen_dbp_noise(
@@ -1,10 +1,13 @@
-use crate::error::Result;
+use crate::{byond::report_error, error::Result};
 use caith::{RollResultType, Roller};
 
 byond_fn!(fn roll_dice(dice) {
     match roll(dice) {
         Ok(result) => return Some(result),
-        Err(error) => return Some(error.to_string())
+        Err(error) => {
+            report_error(&error);
+            return Some(error.to_string())
+        }
     }
 });
 
@@ -1,4 +1,8 @@
-use crate::error::{Error, Result};
+use crate::{
+    byond::{report_error, report_failure},
+    error::{Error, Result},
+    sandbox::check_path,
+};
 use dmi::{
     error::DmiError,
     icon::{Icon, Looping},
@@ -17,11 +21,11 @@ use std::{
 };
 
 byond_fn!(fn dmi_strip_metadata(path) {
-    strip_metadata(path).err()
+    strip_metadata(path).inspect_err(report_error).err()
 });
 
 byond_fn!(fn dmi_create_png(path, width, height, data) {
-    create_png(path, width, height, data).err()
+    create_png(path, width, height, data).inspect_err(report_error).err()
 });
 
 byond_fn!(fn dmi_resize_png(path, width, height, resizetype) {
@@ -33,25 +37,29 @@ byond_fn!(fn dmi_resize_png(path, width, height, resizetype) {
         "triangle" => image::imageops::Triangle,
         _ => image::imageops::Nearest,
     };
-    resize_png(path, width, height, resizetype).err()
+    resize_png(path, width, height, resizetype).inspect_err(report_error).err()
 });
 
 byond_fn!(fn dmi_icon_states(path) {
-    read_states(path).ok()
+    read_states(path).inspect_err(report_error).ok()
 });
 
 byond_fn!(fn dmi_read_metadata(path) {
     match read_metadata(path) {
         Ok(metadata) => Some(metadata),
-        Err(error) => Some(serde_json::to_string(&error.to_string()).unwrap()),
+        Err(error) => {
+            report_error(&error);
+            Some(serde_json::to_string(&error.to_string()).unwrap())
+        }
     }
 });
 
 byond_fn!(fn dmi_inject_metadata(path, metadata) {
-    inject_metadata(path, metadata).err()
+    inject_metadata(path, metadata).inspect_err(report_error).err()
 });
 
 fn strip_metadata(path: &str) -> Result<()> {
+    check_path(path)?;
     let (reader, frame_info, image) = read_png(path)?;
     write_png(path, &reader, &frame_info, &image, true)
 }
@@ -97,6 +105,7 @@ fn write_png(
 }
 
 fn create_png(path: &str, width: &str, height: &str, data: &str) -> Result<()> {
+    check_path(path)?;
     let width = width.parse::<u32>()?;
     let height = height.parse::<u32>()?;
 
@@ -135,6 +144,7 @@ fn resize_png<P: AsRef<Path>>(
     height: &str,
     resizetype: image::imageops::FilterType,
 ) -> std::result::Result<(), Error> {
+    check_path(path.as_ref())?;
     let width = width.parse::<u32>()?;
     let height = height.parse::<u32>()?;
 
@@ -149,6 +159,7 @@ fn resize_png<P: AsRef<Path>>(
 ///
 /// Erroring at any point will produce an empty string
 fn read_states(path: &str) -> Result<String> {
+    check_path(path)?;
     let file = File::open(path).map(BufReader::new)?;
     let decoder = png::Decoder::new(file);
     let reader = decoder.read_info().map_err(|_| Error::InvalidPngData)?;
@@ -214,6 +225,7 @@ struct DmiMetadata {
 }
 
 fn read_metadata(path: &str) -> Result<String> {
+    check_path(path)?;
     let dmi = Icon::load_meta(File::open(path).map(BufReader::new)?)?;
     let metadata = DmiMetadata {
         width: dmi.width,
@@ -246,6 +258,7 @@ fn read_metadata(path: &str) -> Result<String> {
 }
 
 fn inject_metadata(path: &str, metadata: &str) -> Result<()> {
+    check_path(path)?;
     let read_file = File::open(path).map(BufReader::new)?;
     let decoder = png::Decoder::new(read_file);
     let mut reader = decoder.read_info().map_err(|_| Error::InvalidPngData)?;
@@ -308,21 +321,34 @@ fn inject_metadata(path: &str, metadata: &str) -> Result<()> {
 }
 
 byond_fn!(fn create_qr_code_png(path, data) {
+    if let Err(err) = check_path(path) {
+        report_error(&err);
+        return Some(format!("Error: Could not write QR code image to path: {err}"))
+    }
     let code = match QrCode::new(data.as_bytes()) {
         Ok(code) => code,
-        Err(err) => return Some(format!("Error: Could not read data into QR code: {err}"))
+        Err(err) => {
+            report_failure("qrcode", &err);
+            return Some(format!("Error: Could not read data into QR code: {err}"))
+        }
     };
     let image = code.render::<Rgba<u8>>().build();
     match image.save(path) {
         Ok(_) => Some(String::from(path)),
-        Err(err) => Some(format!("Error: Could not write QR code image to path: {err}"))
+        Err(err) => {
+            report_failure("image", &err);
+            Some(format!("Error: Could not write QR code image to path: {err}"))
+        }
     }
 });
 
 byond_fn!(fn create_qr_code_svg(data) {
     let code = match QrCode::new(data.as_bytes()) {
         Ok(code) => code,
-        Err(err) => return Some(format!("Error: Could not read data into QR code: {err}"))
+        Err(err) => {
+            report_failure("qrcode", &err);
+            return Some(format!("Error: Could not read data into QR code: {err}"))
+        }
     };
     let svg_xml = code.render::<svg::Color>().build();
     Some(svg_xml)
@@ -1,4 +1,7 @@
-use crate::error::{Error, Result};
+use crate::{
+    byond::report_error,
+    error::{Error, Result},
+};
 use base64::{Engine, prelude::BASE64_STANDARD};
 use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
 use zeroize::Zeroize;
@@ -16,21 +19,30 @@ byond_fn!(
 byond_fn!(fn ed25519_derive_public_key(secret_key) {
     match derive_ed25519_public_key(secret_key) {
         Ok(public_key) => Some(public_key),
-        Err(error) => Some(format!("ERROR: {error}")),
+        Err(error) => {
+            report_error(&error);
+            Some(format!("ERROR: {error}"))
+        }
     }
 });
 
 byond_fn!(fn ed25519_sign(secret_key, message) {
     match sign_ed25519(secret_key, message.as_bytes()) {
         Ok(signature) => Some(signature),
-        Err(error) => Some(format!("ERROR: {error}")),
+        Err(error) => {
+            report_error(&error);
+            Some(format!("ERROR: {error}"))
+        }
     }
 });
 
 byond_fn!(fn ed25519_verify(public_key, message, signature) {
     match verify_ed25519(public_key, message.as_bytes(), signature) {
         Ok(is_valid) => Some(is_valid.to_string()),
-        Err(error) => Some(format!("ERROR: {error}")),
+        Err(error) => {
+            report_error(&error);
+            Some(format!("ERROR: {error}"))
+        }
     }
 });
 
@@ -34,7 +34,6 @@ pub enum Error {
     #[cfg(feature = "png")]
     #[error(transparent)]
     ImageEncoding(#[from] EncodingError),
-    #[cfg(feature = "http")]
     #[error(transparent)]
     JsonSerialization(#[from] serde_json::Error),
     #[error(transparent)]
@@ -59,6 +58,36 @@ pub enum Error {
     #[cfg(feature = "toml")]
     #[error(transparent)]
     TomlSerialization(#[from] toml_dep::ser::Error),
+    #[cfg(feature = "log")]
+    #[error("The background log writer has stopped")]
+    LogWriterStopped,
+    #[cfg(feature = "log")]
+    #[error("\"{0}\" is set by log_write_json itself and can't be a field")]
+    ReservedLogField(String),
+    #[cfg(feature = "log")]
+    #[error("Invalid timestamp format: {0}")]
+    InvalidTimestampFormat(String),
+    #[cfg(feature = "compress")]
+    #[error("Unknown compression format: {0}")]
+    UnknownCompression(String),
+    #[cfg(feature = "compress")]
+    #[error("Data is neither gzip nor zstd compressed")]
+    UnrecognizedCompression,
+    #[cfg(feature = "compress")]
+    #[error("Compression level {0} is out of range for this format")]
+    InvalidCompressionLevel(i32),
+    #[cfg(feature = "compress")]
+    #[error("Decompressed data is larger than the limit of {0} bytes")]
+    DecompressedTooLarge(u64),
+    #[cfg(feature = "compress")]
+    #[error("Cannot write {0} over itself")]
+    CompressInPlace(String),
+    #[cfg(feature = "csv")]
+    #[error(transparent)]
+    Csv(#[from] csv_dep::Error),
+    #[cfg(feature = "csv")]
+    #[error("{0}")]
+    InvalidCsv(String),
     #[cfg(feature = "unzip")]
     #[error(transparent)]
     Unzip(#[from] ZipError),
@@ -76,7 +105,6 @@ pub enum Error {
     #[cfg(feature = "dmi")]
     #[error(transparent)]
     Dmi(#[from] dmi::error::DmiError),
-    #[cfg(feature = "ed25519")]
     #[error(transparent)]
     Base64Decode(#[from] base64::DecodeError),
     #[cfg(feature = "ed25519")]
@@ -89,10 +117,110 @@ pub enum Error {
         expected: usize,
         actual: usize,
     },
+    #[cfg(feature = "file")]
+    #[error(transparent)]
+    GlobPattern(#[from] glob::PatternError),
+    #[cfg(feature = "file")]
+    #[error("No such handle: {0}")]
+    NoSuchHandle(String),
+    #[cfg(feature = "file")]
+    #[error("Too many open files, close some first.")]
+    TooManyHandles,
+    #[cfg(feature = "file_watch")]
+    #[error(transparent)]
+    Watch(#[from] notify::Error),
+    #[cfg(feature = "file_watch")]
+    #[error("No file watch with handle {0}.")]
+    NoSuchWatch(String),
+    #[cfg(feature = "jobs")]
+    #[error("Unknown job kind: {0}")]
+    InvalidJobKind(String),
+    #[cfg(feature = "jobs")]
+    #[error("Invalid timeout: {0} seconds")]
+    InvalidTimeout(f32),
+    #[cfg(feature = "jobs")]
+    #[error("Job was cancelled.")]
+    JobCancelled,
+    #[error("Path is outside the allowed filesystem roots: {0}")]
+    PathNotAllowed(String),
+    #[error("Allowed filesystem roots can only be set once.")]
+    SandboxAlreadySet,
+    #[error("Invalid metrics option: {0}")]
+    InvalidMetricsOption(String),
     #[error("Panic during function execution: {0}")]
     Panic(String),
 }
 
+impl Error {
+    /// Short, stable name for the kind of error, used by the result envelope.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Null => "null",
+            Self::Utf8 { .. } => "utf8",
+            Self::InvalidFilename => "invalid_filename",
+            Self::Io(_) => "io",
+            Self::InvalidAlgorithm => "invalid_algorithm",
+            #[cfg(feature = "png")]
+            Self::ImageDecoding(_)
+            | Self::ImageEncoding(_)
+            | Self::GenericImage(_)
+            | Self::InvalidPngData => "image",
+            Self::JsonSerialization(_) => "json",
+            Self::ParseInt(_) | Self::ParseFloat(_) => "parse",
+            #[cfg(feature = "http")]
+            Self::Request(_) => "http",
+            #[cfg(feature = "sound_len")]
+            Self::SoundLen(_) => "sound_len",
+            #[cfg(feature = "toml")]
+            Self::TomlDeserialization(_) | Self::TomlSerialization(_) => "toml",
+            #[cfg(feature = "log")]
+            Self::LogWriterStopped
+            | Self::ReservedLogField(_)
+            | Self::InvalidTimestampFormat(_) => "log",
+            #[cfg(feature = "compress")]
+            Self::UnknownCompression(_)
+            | Self::UnrecognizedCompression
+            | Self::InvalidCompressionLevel(_)
+            | Self::DecompressedTooLarge(_)
+            | Self::CompressInPlace(_) => "compress",
+            #[cfg(feature = "csv")]
+            Self::Csv(_) | Self::InvalidCsv(_) => "csv",
+            #[cfg(feature = "unzip")]
+            Self::Unzip(_) => "unzip",
+            #[cfg(feature = "hash")]
+            Self::BadSeed | Self::BadDigits => "totp",
+            #[cfg(feature = "dice")]
+            Self::DiceRoll(_) => "dice",
+            Self::Formatting(_) => "formatting",
+            #[cfg(feature = "dmi")]
+            Self::Dmi(_) => "dmi",
+            Self::Base64Decode(_) => "base64",
+            #[cfg(feature = "ed25519")]
+            Self::Ed25519(_) | Self::InvalidEd25519Length { .. } => "ed25519",
+            #[cfg(feature = "file")]
+            Self::GlobPattern(_) => "glob",
+            #[cfg(feature = "file")]
+            Self::NoSuchHandle(_) => "no_such_handle",
+            #[cfg(feature = "file")]
+            Self::TooManyHandles => "too_many_handles",
+            #[cfg(feature = "file_watch")]
+            Self::Watch(_) => "watch",
+            #[cfg(feature = "file_watch")]
+            Self::NoSuchWatch(_) => "no_such_watch",
+            #[cfg(feature = "jobs")]
+            Self::InvalidJobKind(_) => "invalid_job_kind",
+            #[cfg(feature = "jobs")]
+            Self::InvalidTimeout(_) => "invalid_timeout",
+            #[cfg(feature = "jobs")]
+            Self::JobCancelled => "cancelled",
+            Self::PathNotAllowed(_) => "path_not_allowed",
+            Self::SandboxAlreadySet => "sandbox_already_set",
+            Self::InvalidMetricsOption(_) => "metrics",
+            Self::Panic(_) => "panic",
+        }
+    }
+}
+
 impl From<Utf8Error> for Error {
     fn from(source: Utf8Error) -> Self {
         Self::Utf8 {
@@ -1,11 +1,37 @@
-use crate::error::Result;
+use crate::{
+    byond::{deserialize_byond_bool, failure_json, report_error},
+    error::{Error, Result},
+    jobs::{self, JobKind},
+    sandbox::check_path,
+};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use serde::Deserialize;
+use serde_json::{Value, json};
 use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    ffi::OsString,
     fs::{File, OpenOptions},
-    io::{BufRead, BufReader, BufWriter, Read, Write},
+    io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
 };
+use walkdir::WalkDir;
+
+const DEFAULT_MAX_ENTRIES: usize = 10_000;
+/// How many files `file_open` will keep open at once.
+const MAX_HANDLES: usize = 256;
+/// How many files keep a cached line index for line reads.
+const MAX_LINE_INDEXES: usize = 16;
+/// Line indexes keep the offset of every this many lines, so they stay small
+/// for huge files. Reads scan forward at most this many lines from there.
+const LINE_INDEX_STRIDE: usize = 256;
 
-byond_fn!(fn file_read(path) {
-    read(path).ok()
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+byond_fn!(binary fn file_read(path; encoding) {
+    read_encoded(path, encoding).inspect_err(report_error).ok()
 });
 
 byond_fn!(fn file_exists(path) {
@@ -13,25 +39,350 @@ byond_fn!(fn file_exists(path) {
 });
 
 byond_fn!(fn file_write(data, path) {
-    write(data, path).err()
+    write(data.as_bytes(), path).inspect_err(report_error).err()
+});
+
+byond_fn!(fn file_write_atomic(data, path, backup) {
+    write_atomic(data.as_bytes(), path, backup == "1").inspect_err(report_error).err()
 });
 
 byond_fn!(fn file_append(data, path) {
-    append(data, path).err()
+    append(data.as_bytes(), path).inspect_err(report_error).err()
+});
+
+byond_fn!(fn file_read_base64(path, offset, length) {
+    read_range(path, offset, length)
+        .map(|bytes| BASE64_STANDARD.encode(bytes))
+        .inspect_err(report_error)
+        .ok()
+});
+
+byond_fn!(fn file_write_base64(data, path) {
+    BASE64_STANDARD
+        .decode(data)
+        .map_err(From::from)
+        .and_then(|bytes| write(&bytes, path))
+        .inspect_err(report_error)
+        .err()
+});
+
+byond_fn!(fn file_append_base64(data, path) {
+    BASE64_STANDARD
+        .decode(data)
+        .map_err(From::from)
+        .and_then(|bytes| append(&bytes, path))
+        .inspect_err(report_error)
+        .err()
 });
 
 byond_fn!(fn file_get_line_count(path) {
-    Some(get_line_count(path).ok()?.to_string())
+    Some(get_line_count(path).inspect_err(report_error).ok()?.to_string())
 });
 
 byond_fn!(fn file_seek_line(path, line) {
-    seek_line(path, match line.parse::<usize>() {
-        Ok(line) => line,
-        Err(_) => return None,
-    })
+    line.parse::<usize>()
+        .map_err(Error::from)
+        .and_then(|line| seek_line(path, line))
+        .inspect_err(report_error)
+        .ok()
+        .flatten()
+});
+
+byond_fn!(fn file_read_lines(path, start, count) {
+    let lines = match (start.parse(), count.parse()) {
+        (Ok(start), Ok(count)) => read_lines(path, start, count),
+        (Err(error), _) | (_, Err(error)) => Err(Error::from(error)),
+    };
+    Some(json_result(lines))
+});
+
+byond_fn!(fn file_tail(path, count) {
+    Some(json_result(count.parse().map_err(Error::from).and_then(|count| tail(path, count))))
+});
+
+byond_fn!(fn file_open(path) {
+    Some(open_handle(path).map_or_else(error_json, |handle| handle.to_string()))
+});
+
+byond_fn!(fn file_read_chunk(handle, length, encoding) {
+    let chunk = length
+        .parse()
+        .map_err(Error::from)
+        .and_then(|length| read_chunk(handle, length, encoding));
+    Some(json_result(chunk))
+});
+
+byond_fn!(fn file_read_line(handle) {
+    Some(json_result(read_handle_line(handle)))
+});
+
+byond_fn!(fn file_seek(handle, offset, whence) {
+    Some(json_result(seek_handle(handle, offset, whence)))
+});
+
+byond_fn!(fn file_close(handle) {
+    let closed = handle
+        .parse::<usize>()
+        .ok()
+        .and_then(|handle| HANDLES.with_borrow_mut(|handles| handles.remove(&handle)));
+    Some(closed.is_some().to_string())
+});
+
+byond_fn!(
+    fn file_close_all() {
+        HANDLES.with_borrow_mut(HashMap::clear);
+        Some("")
+    }
+);
+
+byond_fn!(fn file_list_dir(path, options) {
+    Some(json_result(list_dir(path, options)))
+});
+
+// Returns new job-id.
+byond_fn!(fn file_list_dir_async(path, options) {
+    let path = path.to_owned();
+    let options = options.to_owned();
+    Some(jobs::start(JobKind::File, move || {
+        list_dir(&path, &options).map_or_else(|error| job_error_json(&error), |value| value.to_string())
+    }))
+});
+
+byond_fn!(fn file_check(id) {
+    Some(jobs::check(id))
+});
+
+byond_fn!(fn file_copy(from, to) {
+    copy(from, to).err().map(error_json)
+});
+
+byond_fn!(fn file_move(from, to) {
+    rename(from, to).err().map(error_json)
 });
 
+byond_fn!(fn file_delete(path, recursive) {
+    delete(path, recursive == "1").err().map(error_json)
+});
+
+byond_fn!(fn file_mkdir(path) {
+    mkdir(path).err().map(error_json)
+});
+
+byond_fn!(fn file_stat(path) {
+    Some(json_result(stat(path)))
+});
+
+/// Successful results are returned as is, failures as
+/// `{"error": {"kind", "message"}}`.
+fn json_result(result: Result<Value>) -> String {
+    result.map_or_else(error_json, |value| value.to_string())
+}
+
+pub fn error_json(error: Error) -> String {
+    report_error(&error);
+    job_error_json(&error)
+}
+
+/// Like `error_json`, without reporting the error. Jobs run on a worker
+/// thread, where a reported error would never reach the result envelope.
+fn job_error_json(error: &Error) -> String {
+    failure_json(error).to_string()
+}
+
+fn create_parent_dirs(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    Ok(())
+}
+
+fn copy(from: &str, to: &str) -> Result<u64> {
+    check_path(from)?;
+    check_path(to)?;
+    create_parent_dirs(to.as_ref())?;
+    Ok(std::fs::copy(from, to)?)
+}
+
+/// Renames `from` to `to`, falling back to copying when they are on different
+/// filesystems.
+fn rename(from: &str, to: &str) -> Result<()> {
+    check_path(from)?;
+    check_path(to)?;
+    create_parent_dirs(to.as_ref())?;
+    match std::fs::rename(from, to) {
+        Err(error) if error.kind() == std::io::ErrorKind::CrossesDevices => {
+            if !std::fs::metadata(from)?.is_dir() {
+                std::fs::copy(from, to)?;
+                std::fs::remove_file(from)?;
+            } else if let Err(error) = copy_dir(from.as_ref(), to.as_ref()) {
+                // Leave the original alone rather than a half-moved copy.
+                let _ = std::fs::remove_dir_all(to);
+                return Err(error);
+            } else {
+                std::fs::remove_dir_all(from)?;
+            }
+            Ok(())
+        }
+        result => Ok(result?),
+    }
+}
+
+/// Copies a directory and everything in it. Symlinks are copied as whatever
+/// they point to.
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    for entry in WalkDir::new(from).follow_links(true) {
+        let entry = entry.map_err(std::io::Error::from)?;
+        let target = to.join(entry.path().strip_prefix(from).unwrap_or(entry.path()));
+        if entry.file_type().is_dir() {
+            std::fs::create_dir_all(&target)?;
+        } else {
+            std::fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Deletes a file, or a directory. Directories must be empty unless `recursive`
+/// is set. Symlinks are removed rather than followed.
+fn delete(path: &str, recursive: bool) -> Result<()> {
+    check_path(path)?;
+    let file_type = std::fs::symlink_metadata(path)?.file_type();
+    if !file_type.is_dir() {
+        std::fs::remove_file(path)?;
+    } else if recursive {
+        std::fs::remove_dir_all(path)?;
+    } else {
+        std::fs::remove_dir(path)?;
+    }
+    Ok(())
+}
+
+fn mkdir(path: &str) -> Result<()> {
+    check_path(path)?;
+    Ok(std::fs::create_dir_all(path)?)
+}
+
+fn stat(path: &str) -> Result<Value> {
+    check_path(path)?;
+    let metadata = std::fs::symlink_metadata(path)?;
+    let mut stat = metadata_json(&metadata);
+    stat["readonly"] = metadata.permissions().readonly().into();
+    stat["created"] = metadata
+        .created()
+        .ok()
+        .and_then(|created| created.duration_since(UNIX_EPOCH).ok())
+        .map(|created| created.as_secs())
+        .into();
+    Ok(stat)
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct ListOptions {
+    #[serde(deserialize_with = "deserialize_byond_bool")]
+    recursive: bool,
+    glob: Option<String>,
+    max_entries: usize,
+}
+
+impl Default for ListOptions {
+    fn default() -> Self {
+        Self {
+            recursive: false,
+            glob: None,
+            max_entries: DEFAULT_MAX_ENTRIES,
+        }
+    }
+}
+
+/// Lists the entries of a directory as `{"entries": [...], "truncated": bool}`.
+/// Entry names are relative to `path`, with `/` separators.
+fn list_dir(path: &str, options: &str) -> Result<Value> {
+    check_path(path)?;
+    let options: ListOptions = if options.is_empty() {
+        ListOptions::default()
+    } else {
+        serde_json::from_str(options)?
+    };
+    let pattern = options
+        .glob
+        .as_deref()
+        .map(glob::Pattern::new)
+        .transpose()?;
+    if !std::fs::metadata(path)?.is_dir() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotADirectory,
+            format!("{path} is not a directory"),
+        )
+        .into());
+    }
+
+    let mut walker = WalkDir::new(path).min_depth(1).sort_by_file_name();
+    if !options.recursive {
+        walker = walker.max_depth(1);
+    }
+
+    let mut entries = Vec::new();
+    let mut truncated = false;
+    // Entries which can't be read, e.g. for lack of permissions, are skipped.
+    for entry in walker.into_iter().filter_map(|entry| entry.ok()) {
+        let name = relative_name(path, entry.path());
+        if pattern
+            .as_ref()
+            .is_some_and(|pattern| !pattern.matches(&name))
+        {
+            continue;
+        }
+        if entries.len() == options.max_entries {
+            truncated = true;
+            break;
+        }
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        let mut entry = metadata_json(&metadata);
+        entry["name"] = name.into();
+        entries.push(entry);
+    }
+
+    Ok(json!({"entries": entries, "truncated": truncated}))
+}
+
+pub fn relative_name(root: impl AsRef<Path>, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+/// The `type`, `size` and `modified` fields shared by `file_stat` and
+/// `file_list_dir` entries.
+fn metadata_json(metadata: &std::fs::Metadata) -> Value {
+    json!({
+        "type": file_type_name(&metadata.file_type()),
+        "size": metadata.len(),
+        "modified": modified_seconds(metadata),
+    })
+}
+
+fn file_type_name(file_type: &std::fs::FileType) -> &'static str {
+    if file_type.is_symlink() {
+        "symlink"
+    } else if file_type.is_dir() {
+        "dir"
+    } else {
+        "file"
+    }
+}
+
+/// Last modification time in seconds since the unix epoch, if the platform has it.
+fn modified_seconds(metadata: &std::fs::Metadata) -> Option<u64> {
+    let modified = metadata.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_secs())
+}
+
 fn read(path: &str) -> Result<String> {
+    check_path(path)?;
     let file = File::open(path)?;
     let metadata = file.metadata()?;
     let mut file = BufReader::new(file);
@@ -43,51 +394,677 @@ fn read(path: &str) -> Result<String> {
     Ok(content)
 }
 
+/// Reads a whole file. With the base64 encoding the bytes are returned as is
+/// for `byond_fn!` to encode, otherwise as text with carriage returns removed.
+fn read_encoded(path: &str, encoding: &str) -> Result<Vec<u8>> {
+    if encoding == crate::byond::BINARY_BASE64 {
+        read_range(path, "", "")
+    } else {
+        read(path).map(String::into_bytes)
+    }
+}
+
+/// Reads `length` bytes starting at `offset`, as is. An empty offset starts at
+/// the beginning of the file, an empty length reads to the end.
+fn read_range(path: &str, offset: &str, length: &str) -> Result<Vec<u8>> {
+    check_path(path)?;
+    let offset: u64 = if offset.is_empty() {
+        0
+    } else {
+        offset.parse()?
+    };
+    let length: Option<u64> = if length.is_empty() {
+        None
+    } else {
+        Some(length.parse()?)
+    };
+
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut content = Vec::new();
+    match length {
+        Some(length) => file.take(length).read_to_end(&mut content)?,
+        None => file.read_to_end(&mut content)?,
+    };
+
+    Ok(content)
+}
+
 fn exists(path: &str) -> String {
     let path = std::path::Path::new(path);
-    path.exists().to_string()
+    (check_path(path).is_ok() && path.exists()).to_string()
 }
 
-fn write(data: &str, path: &str) -> Result<usize> {
-    let path: &std::path::Path = path.as_ref();
-    if let Some(parent) = path.parent() {
-        std::fs::create_dir_all(parent)?;
-    }
+fn write(data: &[u8], path: &str) -> Result<usize> {
+    check_path(path)?;
+    let path: &Path = path.as_ref();
+    create_parent_dirs(path)?;
 
     let mut file = BufWriter::new(File::create(path)?);
-    let written = file.write(data.as_bytes())?;
+    file.write_all(data)?;
 
     file.flush()?;
     file.into_inner()
         .map_err(|e| std::io::Error::new(e.error().kind(), e.error().to_string()))? // This is god-awful, but the compiler REFUSES to let me get an owned copy of `e`
         .sync_all()?;
 
-    Ok(written)
+    Ok(data.len())
 }
 
-fn append(data: &str, path: &str) -> Result<usize> {
-    let path: &std::path::Path = path.as_ref();
-    if let Some(parent) = path.parent() {
-        std::fs::create_dir_all(parent)?;
+/// Writes `data` to a temporary file next to `path` and renames it into place,
+/// so `path` holds either its old or its new contents even if we crash halfway
+/// through. With `backup`, the old contents are also kept as `path.bak`.
+pub fn write_atomic(data: &[u8], path: &str, backup: bool) -> Result<usize> {
+    check_path(path)?;
+    let path: &Path = path.as_ref();
+    create_parent_dirs(path)?;
+
+    let mut temp_name = OsString::from(".");
+    temp_name.push(path.file_name().ok_or(Error::InvalidFilename)?);
+    temp_name.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let temp_path = path.with_file_name(temp_name);
+
+    let result = replace_with(&temp_path, data, path, backup);
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    result.map(|()| data.len())
+}
+
+fn replace_with(temp_path: &Path, data: &[u8], path: &Path, backup: bool) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(temp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    drop(file);
+
+    if backup && path.exists() {
+        let mut backup_path = path.as_os_str().to_owned();
+        backup_path.push(".bak");
+        std::fs::copy(path, backup_path)?;
     }
+    std::fs::rename(temp_path, path)?;
+
+    // The rename itself only survives a crash once the directory is synced.
+    #[cfg(unix)]
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => File::open(parent)?.sync_all()?,
+        _ => File::open(".")?.sync_all()?,
+    }
+    Ok(())
+}
+
+fn append(data: &[u8], path: &str) -> Result<usize> {
+    check_path(path)?;
+    let path: &Path = path.as_ref();
+    create_parent_dirs(path)?;
 
     let mut file = BufWriter::new(OpenOptions::new().append(true).create(true).open(path)?);
-    let written = file.write(data.as_bytes())?;
+    file.write_all(data)?;
 
     file.flush()?;
     file.into_inner()
         .map_err(|e| std::io::Error::new(e.error().kind(), e.error().to_string()))?
         .sync_all()?;
 
-    Ok(written)
+    Ok(data.len())
 }
 
 fn get_line_count(path: &str) -> Result<u32> {
+    check_path(path)?;
+    with_line_index(path, |index| Ok(index.line_count() as u32))
+}
+
+/// The line numbered `line`, or `None` past the end of the file.
+fn seek_line(path: &str, line: usize) -> Result<Option<String>> {
+    check_path(path)?;
+    let bytes = with_line_index(path, |index| index.read_lines(path, line, 1))?;
+    bytes
+        .into_iter()
+        .next()
+        .map(|bytes| String::from_utf8(bytes).map_err(|error| error.utf8_error().into()))
+        .transpose()
+}
+
+/// Opens a file for reading, keeping it open until `file_close`.
+fn open_handle(path: &str) -> Result<usize> {
+    check_path(path)?;
+    if HANDLES.with_borrow(HashMap::len) >= MAX_HANDLES {
+        return Err(Error::TooManyHandles);
+    }
     let file = BufReader::new(File::open(path)?);
-    Ok(file.lines().count() as u32)
+    let handle = NEXT_HANDLE.replace(NEXT_HANDLE.get() + 1);
+    HANDLES.with_borrow_mut(|handles| handles.insert(handle, file));
+    Ok(handle)
+}
+
+fn with_handle<R>(handle: &str, f: impl FnOnce(&mut BufReader<File>) -> Result<R>) -> Result<R> {
+    HANDLES.with_borrow_mut(|handles| {
+        let file = handle
+            .parse::<usize>()
+            .ok()
+            .and_then(|handle| handles.get_mut(&handle))
+            .ok_or_else(|| Error::NoSuchHandle(handle.to_owned()))?;
+        f(file)
+    })
+}
+
+/// Reads up to `length` bytes as `{"data", "eof"}`. With the base64 encoding
+/// the bytes are returned as is, otherwise as text, in which case a character
+/// cut in half by the end of the chunk is left for the next read. Every read
+/// moves forward by at least one byte, or one character as text, so a loop
+/// reading until eof always ends.
+fn read_chunk(handle: &str, length: u64, encoding: &str) -> Result<Value> {
+    with_handle(handle, |file| {
+        let mut chunk = Vec::new();
+        file.by_ref().take(length.max(1)).read_to_end(&mut chunk)?;
+
+        let data = if encoding == crate::byond::BINARY_BASE64 {
+            BASE64_STANDARD.encode(&chunk)
+        } else {
+            let mut cut = incomplete_char_len(&chunk);
+            // Too short for even one character, so finish that character.
+            while cut > 0 && cut == chunk.len() {
+                let mut byte = [0];
+                if file.read(&mut byte)? == 0 {
+                    break;
+                }
+                chunk.push(byte[0]);
+                cut = incomplete_char_len(&chunk);
+            }
+            if cut > 0 && cut < chunk.len() {
+                file.seek_relative(-(cut as i64))?;
+                chunk.truncate(chunk.len() - cut);
+            }
+            String::from_utf8_lossy(&chunk).into_owned()
+        };
+        let eof = file.fill_buf()?.is_empty();
+        Ok(json!({"data": data, "eof": eof}))
+    })
+}
+
+/// How many bytes at the end of `bytes` are the start of a UTF-8 character
+/// which is cut off.
+fn incomplete_char_len(bytes: &[u8]) -> usize {
+    match std::str::from_utf8(bytes) {
+        Err(error) if error.error_len().is_none() => bytes.len() - error.valid_up_to(),
+        _ => 0,
+    }
+}
+
+/// Reads the next line as `{"line"}`, without its line ending. The line is
+/// null once the end of the file is reached.
+fn read_handle_line(handle: &str) -> Result<Value> {
+    with_handle(handle, |file| {
+        let mut line = Vec::new();
+        if file.read_until(b'\n', &mut line)? == 0 {
+            return Ok(json!({"line": null}));
+        }
+        let line = line.strip_suffix(b"\n").unwrap_or(&line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        Ok(json!({"line": String::from_utf8_lossy(line)}))
+    })
+}
+
+/// Moves the read position, returning it as `{"position"}`. `whence` is
+/// "start" (the default), "current" or "end".
+fn seek_handle(handle: &str, offset: &str, whence: &str) -> Result<Value> {
+    let offset: i64 = offset.parse()?;
+    let target = match whence {
+        "current" => SeekFrom::Current(offset),
+        "end" => SeekFrom::End(offset),
+        "" | "start" => SeekFrom::Start(offset.try_into().map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Cannot seek to negative offset {offset}"),
+            )
+        })?),
+        _ => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Unknown whence \"{whence}\", expected \"start\", \"current\" or \"end\""),
+            )
+            .into());
+        }
+    };
+    with_handle(handle, |file| {
+        let position = file.seek(target)?;
+        Ok(json!({"position": position}))
+    })
+}
+
+/// Reads up to `count` lines starting at line `start`, counting from zero, as
+/// `{"start", "lines": [...], "total_lines"}`.
+fn read_lines(path: &str, start: usize, count: usize) -> Result<Value> {
+    check_path(path)?;
+    with_line_index(path, |index| lines_json(path, index, start, count))
+}
+
+/// Reads the last `count` lines, in the same format as `read_lines`.
+fn tail(path: &str, count: usize) -> Result<Value> {
+    check_path(path)?;
+    with_line_index(path, |index| {
+        let start = index.line_count().saturating_sub(count);
+        lines_json(path, index, start, count)
+    })
+}
+
+fn lines_json(path: &str, index: &LineIndex, start: usize, count: usize) -> Result<Value> {
+    let lines: Vec<String> = index
+        .read_lines(path, start, count)?
+        .iter()
+        .map(|line| String::from_utf8_lossy(line).into_owned())
+        .collect();
+    Ok(json!({
+        "start": start,
+        "lines": lines,
+        "total_lines": index.line_count(),
+    }))
+}
+
+/// Byte offsets of the start of every `LINE_INDEX_STRIDE`th line in a file,
+/// so any range of lines can be read with one seek and a short scan instead
+/// of scanning from the top.
+struct LineIndex {
+    modified: Option<SystemTime>,
+    len: u64,
+    lines: usize,
+    checkpoints: Vec<u64>,
+    last_used: u64,
+}
+
+impl LineIndex {
+    fn build(path: &Path, metadata: &std::fs::Metadata) -> Result<Self> {
+        let mut reader = BufReader::with_capacity(64 * 1024, File::open(path)?);
+        let mut index = Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+            lines: 0,
+            checkpoints: Vec::new(),
+            last_used: 0,
+        };
+        if index.len > 0 {
+            index.line_starts_at(0);
+        }
+        let mut position = 0;
+        loop {
+            let buffer = reader.fill_buf()?;
+            if buffer.is_empty() {
+                break;
+            }
+            let newlines = buffer
+                .iter()
+                .enumerate()
+                .filter(|(_, byte)| **byte == b'\n');
+            for (newline, _) in newlines {
+                let next_line = position + newline as u64 + 1;
+                if next_line < index.len {
+                    index.line_starts_at(next_line);
+                }
+            }
+            let read = buffer.len();
+            position += read as u64;
+            reader.consume(read);
+        }
+        Ok(index)
+    }
+
+    fn line_starts_at(&mut self, offset: u64) {
+        if self.lines.is_multiple_of(LINE_INDEX_STRIDE) {
+            self.checkpoints.push(offset);
+        }
+        self.lines += 1;
+    }
+
+    fn is_current(&self, metadata: &std::fs::Metadata) -> bool {
+        self.len == metadata.len() && self.modified == metadata.modified().ok()
+    }
+
+    fn line_count(&self) -> usize {
+        self.lines
+    }
+
+    /// Reads lines `start..start + count` without their line endings.
+    fn read_lines(&self, path: &str, start: usize, count: usize) -> Result<Vec<Vec<u8>>> {
+        let end = start.saturating_add(count).min(self.line_count());
+        if start >= end {
+            return Ok(Vec::new());
+        }
+        let checkpoint = start / LINE_INDEX_STRIDE;
+        let from = self.checkpoints[checkpoint];
+
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(from))?;
+        let mut reader = BufReader::new(file.take(self.len - from));
+        for _ in checkpoint * LINE_INDEX_STRIDE..start {
+            reader.skip_until(b'\n')?;
+        }
+        let mut lines = Vec::with_capacity(end - start);
+        for _ in start..end {
+            let mut line = Vec::new();
+            reader.read_until(b'\n', &mut line)?;
+            if line.ends_with(b"\n") {
+                line.pop();
+            }
+            if line.ends_with(b"\r") {
+                line.pop();
+            }
+            lines.push(line);
+        }
+        Ok(lines)
+    }
 }
 
-fn seek_line(path: &str, line: usize) -> Option<String> {
-    let file = BufReader::new(File::open(path).ok()?);
-    file.lines().nth(line).and_then(std::result::Result::ok)
+thread_local! {
+    static HANDLES: RefCell<HashMap<usize, BufReader<File>>> = RefCell::new(HashMap::new());
+    static NEXT_HANDLE: Cell<usize> = const { Cell::new(1) };
+    static LINE_INDEXES: RefCell<HashMap<PathBuf, LineIndex>> = RefCell::new(HashMap::new());
+    static LINE_INDEX_CLOCK: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Runs `f` with the line index of `path`, rebuilding it if the file's size or
+/// modification time changed since it was cached. Indexes are cached by the
+/// canonical path, so every way of naming a file shares one.
+fn with_line_index<R>(path: &str, f: impl FnOnce(&LineIndex) -> Result<R>) -> Result<R> {
+    let key = std::fs::canonicalize(path)?;
+    let metadata = std::fs::metadata(&key)?;
+    let now = LINE_INDEX_CLOCK.replace(LINE_INDEX_CLOCK.get() + 1);
+    LINE_INDEXES.with_borrow_mut(|indexes| {
+        if !indexes
+            .get(&key)
+            .is_some_and(|index| index.is_current(&metadata))
+        {
+            if indexes.len() >= MAX_LINE_INDEXES && !indexes.contains_key(&key) {
+                let oldest = indexes
+                    .iter()
+                    .min_by_key(|(_, index)| index.last_used)
+                    .map(|(path, _)| path.clone());
+                if let Some(oldest) = oldest {
+                    indexes.remove(&oldest);
+                }
+            }
+            indexes.insert(key.clone(), LineIndex::build(&key, &metadata)?);
+        }
+        let index = indexes.get_mut(&key).expect("index was just inserted");
+        index.last_used = now;
+        f(index)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rustg-file-test-{}", std::process::id()));
+        let path = dir.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn binary_read_keeps_every_byte() {
+        let path = temp_file("binary_read.bin", b"\x00\x01\r\n\xff");
+        let path = path.to_str().unwrap();
+        assert_eq!(read_encoded(path, "base64").unwrap(), [0, 1, 13, 10, 255]);
+        assert!(read_encoded(path, "").is_err());
+
+        let path = temp_file("text_read.txt", b"a\r\nb");
+        let path = path.to_str().unwrap();
+        assert_eq!(read_encoded(path, "").unwrap(), b"a\nb");
+    }
+
+    #[test]
+    fn byte_ranges_stop_at_the_file_boundaries() {
+        let path = temp_file("byte_ranges.bin", &[0, 1, 2, 3, 4]);
+        let path = path.to_str().unwrap();
+        let range = |offset: &str, length: &str| read_range(path, offset, length).unwrap();
+        assert_eq!(range("", ""), [0, 1, 2, 3, 4]);
+        assert_eq!(range("0", "5"), [0, 1, 2, 3, 4]);
+        assert_eq!(range("1", "3"), [1, 2, 3]);
+        assert_eq!(range("4", ""), [4]);
+        assert_eq!(range("3", "10"), [3, 4]);
+        assert!(range("2", "0").is_empty());
+        assert!(range("5", "").is_empty());
+        assert!(range("9", "1").is_empty());
+        assert!(read_range(path, "-1", "").is_err());
+        assert!(read_range(path, "", "x").is_err());
+    }
+
+    #[test]
+    fn listing_filters_by_glob_and_stops_at_max_entries() {
+        let dir = temp_file("listing/maps/a.dmm", b"")
+            .parent()
+            .unwrap()
+            .to_owned();
+        temp_file("listing/maps/b.dmm", b"");
+        temp_file("listing/maps/notes.txt", b"");
+        temp_file("listing/maps/sub/c.dmm", b"");
+        let dir = dir.to_str().unwrap();
+        let names = |options: &str| {
+            let listing = list_dir(dir, options).unwrap();
+            let names: Vec<String> = listing["entries"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|entry| entry["name"].as_str().unwrap().to_owned())
+                .collect();
+            (names, listing["truncated"].as_bool().unwrap())
+        };
+
+        assert_eq!(
+            names(""),
+            (
+                vec![
+                    "a.dmm".into(),
+                    "b.dmm".into(),
+                    "notes.txt".into(),
+                    "sub".into()
+                ],
+                false
+            )
+        );
+        assert_eq!(
+            names(r#"{"glob": "*.dmm"}"#),
+            (vec!["a.dmm".into(), "b.dmm".into()], false)
+        );
+        assert_eq!(
+            names(r#"{"glob": "**/*.dmm", "recursive": 1}"#),
+            (
+                vec!["a.dmm".into(), "b.dmm".into(), "sub/c.dmm".into()],
+                false
+            )
+        );
+        assert_eq!(
+            names(r#"{"glob": "**/*.dmm", "recursive": true}"#),
+            names(r#"{"glob": "**/*.dmm", "recursive": 1}"#)
+        );
+        assert!(list_dir(dir, r#"{"recursive": "yes"}"#).is_err());
+        assert_eq!(
+            names(r#"{"glob": "*.dmm", "max_entries": 2}"#),
+            (vec!["a.dmm".into(), "b.dmm".into()], false)
+        );
+        assert_eq!(
+            names(r#"{"glob": "*.dmm", "max_entries": 1}"#),
+            (vec!["a.dmm".into()], true)
+        );
+        assert_eq!(names(r#"{"max_entries": 0}"#), (vec![], true));
+        assert!(list_dir(dir, r#"{"glob": "["}"#).is_err());
+    }
+
+    #[test]
+    fn directories_are_copied_recursively() {
+        let from = temp_file("copy_dir/from/a.txt", b"a")
+            .parent()
+            .unwrap()
+            .to_owned();
+        temp_file("copy_dir/from/sub/b.txt", b"b");
+        let to = from.with_file_name("to");
+        copy_dir(&from, &to).unwrap();
+        assert_eq!(std::fs::read(to.join("a.txt")).unwrap(), b"a");
+        assert_eq!(std::fs::read(to.join("sub/b.txt")).unwrap(), b"b");
+    }
+
+    #[test]
+    fn atomic_writes_replace_the_file_and_keep_a_backup_on_request() {
+        let path = temp_file("atomic/config.txt", b"old");
+        let backup = path.with_file_name("config.txt.bak");
+        let dir = path.parent().unwrap().to_owned();
+        let path = path.to_str().unwrap();
+
+        assert_eq!(write_atomic(b"new", path, false).unwrap(), 3);
+        assert_eq!(std::fs::read(path).unwrap(), b"new");
+        assert!(!backup.exists());
+
+        write_atomic(b"newer", path, true).unwrap();
+        assert_eq!(std::fs::read(path).unwrap(), b"newer");
+        assert_eq!(std::fs::read(&backup).unwrap(), b"new");
+
+        // Nothing is backed up for a file which didn't exist yet.
+        let fresh = dir.join("fresh.txt");
+        write_atomic(b"fresh", fresh.to_str().unwrap(), true).unwrap();
+        assert_eq!(std::fs::read(&fresh).unwrap(), b"fresh");
+        assert!(!dir.join("fresh.txt.bak").exists());
+
+        // No temporary files are left behind.
+        let mut names: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["config.txt", "config.txt.bak", "fresh.txt"]);
+    }
+
+    #[test]
+    fn text_chunks_always_move_forward_a_whole_character() {
+        let path = temp_file("chunks.txt", "aé€".as_bytes());
+        let handle = open_handle(path.to_str().unwrap()).unwrap().to_string();
+        let mut chunks = Vec::new();
+        loop {
+            let chunk = read_chunk(&handle, 1, "").unwrap();
+            chunks.push(chunk["data"].as_str().unwrap().to_owned());
+            if chunk["eof"] == true {
+                break;
+            }
+        }
+        assert_eq!(chunks, ["a", "é", "€"]);
+
+        seek_handle(&handle, "0", "start").unwrap();
+        assert_eq!(read_chunk(&handle, 2, "").unwrap()["data"], "a");
+        assert_eq!(read_chunk(&handle, 0, "").unwrap()["data"], "é");
+        assert_eq!(seek_handle(&handle, "-3", "end").unwrap()["position"], 3);
+        assert!(seek_handle(&handle, "-1", "start").is_err());
+        assert!(seek_handle(&handle, "1", "cur").is_err());
+        assert_eq!(seek_handle(&handle, "3", "").unwrap()["position"], 3);
+        assert_eq!(read_chunk(&handle, 16, "").unwrap()["data"], "€");
+    }
+
+    fn lines(listing: Value) -> (Vec<String>, u64, u64) {
+        let lines = listing["lines"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|line| line.as_str().unwrap().to_owned())
+            .collect();
+        (
+            lines,
+            listing["start"].as_u64().unwrap(),
+            listing["total_lines"].as_u64().unwrap(),
+        )
+    }
+
+    #[test]
+    fn line_reads_handle_crlf_and_a_missing_final_newline() {
+        let path = temp_file("lines_crlf.txt", b"one\r\ntwo\r\n\r\nfour");
+        let path = path.to_str().unwrap();
+        assert_eq!(
+            lines(read_lines(path, 0, 10).unwrap()),
+            (
+                vec!["one".into(), "two".into(), "".into(), "four".into()],
+                0,
+                4
+            )
+        );
+        assert_eq!(
+            lines(read_lines(path, 1, 2).unwrap()),
+            (vec!["two".into(), "".into()], 1, 4)
+        );
+        assert_eq!(lines(read_lines(path, 9, 2).unwrap()), (vec![], 9, 4));
+        assert_eq!(seek_line(path, 1).unwrap(), Some("two".into()));
+        assert_eq!(seek_line(path, 9).unwrap(), None);
+        assert_eq!(
+            lines(tail(path, 2).unwrap()),
+            (vec!["".into(), "four".into()], 2, 4)
+        );
+        assert_eq!(lines(tail(path, 10).unwrap()).0.len(), 4);
+
+        let path = temp_file("lines_trailing.txt", b"one\ntwo\n");
+        let path = path.to_str().unwrap();
+        assert_eq!(lines(tail(path, 1).unwrap()), (vec!["two".into()], 1, 2));
+
+        let path = temp_file("lines_binary.txt", b"\xff\n");
+        assert!(seek_line(path.to_str().unwrap(), 0).is_err());
+
+        let path = temp_file("lines_empty.txt", b"");
+        assert_eq!(
+            lines(tail(path.to_str().unwrap(), 1).unwrap()),
+            (vec![], 0, 0)
+        );
+    }
+
+    #[test]
+    fn line_reads_cross_index_checkpoints() {
+        let text: String = (0..LINE_INDEX_STRIDE * 2 + 10)
+            .map(|n| format!("line {n}\n"))
+            .collect();
+        let path = temp_file("lines_long.txt", text.as_bytes());
+        let path = path.to_str().unwrap();
+        let start = LINE_INDEX_STRIDE - 1;
+        let (read, _, total) = lines(read_lines(path, start, 3).unwrap());
+        assert_eq!(total as usize, LINE_INDEX_STRIDE * 2 + 10);
+        assert_eq!(
+            read,
+            (start..start + 3)
+                .map(|n| format!("line {n}"))
+                .collect::<Vec<_>>()
+        );
+        let (read, _, _) = lines(tail(path, 1).unwrap());
+        assert_eq!(read, [format!("line {}", LINE_INDEX_STRIDE * 2 + 9)]);
+    }
+
+    #[test]
+    fn line_index_is_rebuilt_after_an_append() {
+        let path = temp_file("lines_append.txt", b"one\n");
+        let path = path.to_str().unwrap();
+        assert_eq!(lines(tail(path, 5).unwrap()), (vec!["one".into()], 0, 1));
+
+        append(b"two\nthree", path).unwrap();
+        assert_eq!(
+            lines(tail(path, 5).unwrap()),
+            (vec!["one".into(), "two".into(), "three".into()], 0, 3)
+        );
+
+        // Another name for the same file shares its index.
+        let dir = Path::new(path).parent().unwrap();
+        let alias = dir
+            .join("..")
+            .join(dir.file_name().unwrap())
+            .join("lines_append.txt");
+        assert_eq!(
+            lines(tail(alias.to_str().unwrap(), 1).unwrap()).0,
+            ["three"]
+        );
+        let key = std::fs::canonicalize(path).unwrap();
+        LINE_INDEXES.with_borrow(|indexes| {
+            assert!(indexes.contains_key(&key));
+            assert!(!indexes.contains_key(&alias));
+        });
+    }
 }
@@ -0,0 +1,251 @@
+use crate::{
+    error::{Error, Result},
+    file::{error_json, relative_name},
+    sandbox::check_path,
+};
+use notify::{
+    Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher,
+    event::{ModifyKind, RenameMode},
+};
+use serde_json::json;
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, TrySendError, sync_channel},
+    },
+};
+
+/// Events held for a watch between polls. Past this, new events are dropped
+/// and the next poll reports `"overflowed": true`.
+const MAX_QUEUED_EVENTS: usize = 16 * 1024;
+
+struct Watch {
+    // Dropping the watcher stops its background thread.
+    _watcher: RecommendedWatcher,
+    /// What event paths are made relative to: the watched directory, or the
+    /// directory of the watched file.
+    root: PathBuf,
+    events: Receiver<notify::Result<Event>>,
+    overflowed: Arc<AtomicBool>,
+}
+
+thread_local! {
+    static WATCHES: RefCell<HashMap<usize, Watch>> = RefCell::new(HashMap::new());
+    static NEXT_HANDLE: Cell<usize> = const { Cell::new(1) };
+}
+
+byond_fn!(fn file_watch(path, recursive) {
+    Some(watch(path, recursive == "1").map_or_else(error_json, |handle| handle.to_string()))
+});
+
+byond_fn!(fn file_watch_poll(handle) {
+    Some(poll(handle).unwrap_or_else(error_json))
+});
+
+byond_fn!(fn file_watch_stop(handle) {
+    let removed = handle
+        .parse::<usize>()
+        .ok()
+        .and_then(|handle| WATCHES.with_borrow_mut(|watches| watches.remove(&handle)));
+    Some(removed.is_some().to_string())
+});
+
+fn watch(path: &str, recursive: bool) -> Result<usize> {
+    check_path(path)?;
+    let root = std::fs::canonicalize(path)?;
+
+    let (tx, events) = sync_channel(MAX_QUEUED_EVENTS);
+    let overflowed = Arc::new(AtomicBool::new(false));
+    let overflow_flag = overflowed.clone();
+    let mut watcher = notify::recommended_watcher(move |event| {
+        if let Err(TrySendError::Full(_)) = tx.try_send(event) {
+            overflow_flag.store(true, Ordering::Relaxed);
+        }
+    })?;
+    let mode = if recursive {
+        RecursiveMode::Recursive
+    } else {
+        RecursiveMode::NonRecursive
+    };
+    watcher.watch(&root, mode)?;
+    // Events for a single file are reported by its name.
+    let root = match root.parent() {
+        Some(parent) if !root.is_dir() => parent.to_owned(),
+        _ => root,
+    };
+
+    let handle = NEXT_HANDLE.replace(NEXT_HANDLE.get() + 1);
+    WATCHES.with_borrow_mut(|watches| {
+        watches.insert(
+            handle,
+            Watch {
+                _watcher: watcher,
+                root,
+                events,
+                overflowed,
+            },
+        )
+    });
+    Ok(handle)
+}
+
+/// Drains a watch's events as `{"events": [{"kind", "path"}], "errors": [...],
+/// "overflowed": bool}`, with paths relative to the watched directory, or the
+/// file's name if a single file is watched.
+/// Repeats of the same change within one batch are only reported once.
+fn poll(handle: &str) -> Result<String> {
+    WATCHES.with_borrow(|watches| {
+        let watch = handle
+            .parse::<usize>()
+            .ok()
+            .and_then(|handle| watches.get(&handle))
+            .ok_or_else(|| Error::NoSuchWatch(handle.to_owned()))?;
+
+        let mut seen = HashSet::new();
+        let mut events = Vec::new();
+        let mut errors = Vec::new();
+        for event in watch.events.try_iter() {
+            let event = match event {
+                Ok(event) => event,
+                Err(error) => {
+                    errors.push(error.to_string());
+                    continue;
+                }
+            };
+            for (kind, path) in classify(&event) {
+                let path = relative_name(&watch.root, &path);
+                if seen.insert((kind, path.clone())) {
+                    events.push(json!({"kind": kind, "path": path}));
+                }
+            }
+        }
+
+        Ok(json!({
+            "events": events,
+            "errors": errors,
+            "overflowed": watch.overflowed.swap(false, Ordering::Relaxed),
+        })
+        .to_string())
+    })
+}
+
+/// Boils notify's events down to created, modified and deleted. A rename is
+/// the old path being deleted and the new one created.
+fn classify(event: &Event) -> Vec<(&'static str, PathBuf)> {
+    let kinds: &[&'static str] = match event.kind {
+        EventKind::Create(_) => &["created"],
+        EventKind::Remove(_) => &["deleted"],
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) => &["deleted"],
+        EventKind::Modify(ModifyKind::Name(RenameMode::To)) => &["created"],
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => &["deleted", "created"],
+        EventKind::Modify(ModifyKind::Name(_)) => {
+            // Without knowing which side of the rename this is, check.
+            return event
+                .paths
+                .iter()
+                .map(|path| {
+                    let kind = if path.exists() { "created" } else { "deleted" };
+                    (kind, path.clone())
+                })
+                .collect();
+        }
+        EventKind::Modify(_) => &["modified"],
+        _ => &[],
+    };
+    match kinds {
+        [kind] => event
+            .paths
+            .iter()
+            .map(|path| (*kind, path.clone()))
+            .collect(),
+        _ => kinds
+            .iter()
+            .zip(&event.paths)
+            .map(|(kind, path)| (*kind, path.clone()))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{AccessKind, CreateKind, DataChange, RemoveKind};
+    use std::time::{Duration, Instant};
+
+    fn kinds(kind: EventKind, paths: &[&str]) -> Vec<(&'static str, PathBuf)> {
+        let event = paths
+            .iter()
+            .fold(Event::new(kind), |event, path| event.add_path(path.into()));
+        classify(&event)
+    }
+
+    #[test]
+    fn events_are_classified_as_created_modified_or_deleted() {
+        assert_eq!(
+            kinds(EventKind::Create(CreateKind::File), &["a"]),
+            [("created", "a".into())]
+        );
+        assert_eq!(
+            kinds(EventKind::Remove(RemoveKind::Any), &["a", "b"]),
+            [("deleted", "a".into()), ("deleted", "b".into())]
+        );
+        assert_eq!(
+            kinds(
+                EventKind::Modify(ModifyKind::Data(DataChange::Content)),
+                &["a"]
+            ),
+            [("modified", "a".into())]
+        );
+        assert_eq!(
+            kinds(
+                EventKind::Modify(ModifyKind::Name(RenameMode::From)),
+                &["a"]
+            ),
+            [("deleted", "a".into())]
+        );
+        assert_eq!(
+            kinds(EventKind::Modify(ModifyKind::Name(RenameMode::To)), &["b"]),
+            [("created", "b".into())]
+        );
+        assert_eq!(
+            kinds(
+                EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+                &["a", "b"]
+            ),
+            [("deleted", "a".into()), ("created", "b".into())]
+        );
+        assert!(kinds(EventKind::Access(AccessKind::Any), &["a"]).is_empty());
+    }
+
+    #[test]
+    fn unknown_watches_are_reported_as_such() {
+        assert_eq!(poll("0").unwrap_err().kind(), "no_such_watch");
+    }
+
+    #[test]
+    fn single_files_are_reported_by_name() {
+        let dir = std::env::temp_dir().join(format!("rustg-watch-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("watched.txt");
+        std::fs::write(&path, "").unwrap();
+
+        let handle = watch(path.to_str().unwrap(), false).unwrap().to_string();
+        std::fs::write(&path, "changed").unwrap();
+
+        let started = Instant::now();
+        let events = loop {
+            let polled: serde_json::Value = serde_json::from_str(&poll(&handle).unwrap()).unwrap();
+            let events = polled["events"].as_array().unwrap().clone();
+            if !events.is_empty() || started.elapsed() > Duration::from_secs(5) {
+                break events;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        };
+        assert!(!events.is_empty());
+        assert!(events.iter().all(|event| event["path"] == "watched.txt"));
+    }
+}
@@ -1,43 +1,77 @@
+use crate::byond::report_failure;
 use chrono::{TimeZone, Utc};
-use std::{fs, path::Path};
+use std::{error::Error, fs, path::Path};
 
 byond_fn!(fn rg_git_revparse(rev) {
     let repository = match gix::open(".") {
         Ok(repo) => repo,
         Err(err) => {
+            report_failure("git", &err);
             return Some(format!("failed to open repository: {err}"));
         }
     };
-    repository.rev_parse_single(rev).ok().map(|object| object.to_string())
+    repository
+        .rev_parse_single(rev)
+        .inspect_err(|err| report_failure("git", err))
+        .ok()
+        .map(|object| object.to_string())
 });
 
 byond_fn!(fn rg_git_commit_date(rev, format) {
     let repository = match gix::open(".") {
         Ok(repo) => repo,
         Err(err) => {
+            report_failure("git", &err);
             return Some(format!("failed to open repository: {err}"));
         }
     };
-    let rev = repository.rev_parse_single(rev).ok()?;
-    let object = rev.object().ok()?;
-    let commit = object.try_into_commit().ok()?;
-    let commit_time = commit.committer().ok()?.time().ok()?.seconds;
-    let datetime = Utc.timestamp_opt(commit_time, 0).latest()?;
-    Some(datetime.format(format).to_string())
+    commit_date(&repository, rev, format)
+        .inspect_err(|err| report_failure("git", err))
+        .ok()
 });
 
 byond_fn!(fn rg_git_commit_date_head(format) {
+    commit_date_head(format)
+        .inspect_err(|err| report_failure("git", err))
+        .ok()
+});
+
+fn commit_date(
+    repository: &gix::Repository,
+    rev: &str,
+    format: &str,
+) -> Result<String, Box<dyn Error>> {
+    let rev = repository.rev_parse_single(rev)?;
+    let object = rev.object()?;
+    let commit = object.try_into_commit()?;
+    let commit_time = commit.committer()?.time()?.seconds;
+    let datetime = Utc
+        .timestamp_opt(commit_time, 0)
+        .latest()
+        .ok_or("commit time out of range")?;
+    Ok(datetime.format(format).to_string())
+}
+
+fn commit_date_head(format: &str) -> Result<String, Box<dyn Error>> {
     let head_log_path = Path::new(".git").join("logs").join("HEAD");
-    let head_log = fs::metadata(&head_log_path).ok()?;
+    let head_log = fs::metadata(&head_log_path)?;
     if !head_log.is_file() {
-        return None;
+        return Err(format!("{} is not a file", head_log_path.display()).into());
     }
-    let log_entries = fs::read_to_string(&head_log_path).ok()?;
+    let log_entries = fs::read_to_string(&head_log_path)?;
     let mut log_entries = log_entries.split('\n');
-    let last_entry = log_entries.next_back()?.split_ascii_whitespace().collect::<Vec<_>>();
-    if last_entry.len() < 5 { // 5 is the timestamp
-        return None;
+    let last_entry = log_entries
+        .next_back()
+        .unwrap_or_default()
+        .split_ascii_whitespace()
+        .collect::<Vec<_>>();
+    if last_entry.len() < 5 {
+        // 5 is the timestamp
+        return Err("malformed HEAD log entry".into());
     }
-    let datetime = Utc.timestamp_opt(last_entry[4].parse().ok()?, 0).latest()?;
-    Some(datetime.format(format).to_string())
-});
+    let datetime = Utc
+        .timestamp_opt(last_entry[4].parse()?, 0)
+        .latest()
+        .ok_or("commit time out of range")?;
+    Ok(datetime.format(format).to_string())
+}
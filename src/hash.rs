@@ -1,4 +1,8 @@
-use crate::error::{Error, Result};
+use crate::{
+    byond::{report_error, report_failure},
+    error::{Error, Result},
+    sandbox::check_path,
+};
 use base64::Engine;
 use const_random::const_random;
 const XXHASH_SEED: u64 = const_random!(u64);
@@ -25,34 +29,49 @@ const DIGITS_POWER: [u32; 9] = [
 ];
 
 byond_fn!(fn hash_string(algorithm, string) {
-    string_hash(algorithm, string).ok()
+    string_hash(algorithm, string).inspect_err(report_error).ok()
 });
 
-byond_fn!(fn decode_base64(string) {
-    base64::prelude::BASE64_STANDARD.decode(string).ok()
+byond_fn!(binary fn decode_base64(string) {
+    base64::prelude::BASE64_STANDARD
+        .decode(string)
+        .map_err(Error::from)
+        .inspect_err(report_error)
+        .ok()
 });
 
-byond_fn!(fn decode_base32(string, padding) {
-    base32::decode(base32::Alphabet::Rfc4648 { padding: padding == "1" }, string)
+byond_fn!(binary fn decode_base32(string, padding) {
+    let decoded = base32::decode(base32::Alphabet::Rfc4648 { padding: padding == "1" }, string);
+    if decoded.is_none() {
+        report_failure("base32", "Invalid base32 data.");
+    }
+    decoded
 });
 
 byond_fn!(fn hash_file(algorithm, string) {
-    file_hash(algorithm, string).ok()
+    file_hash(algorithm, string).inspect_err(report_error).ok()
 });
 
 byond_fn!(fn generate_totp(algorithm, base32_seed) {
     match totp_generate(algorithm, base32_seed, 0, TOTP_DIGITS, None) {
         Ok(value) => Some(value),
-        Err(error) => Some(format!("ERROR: {error:?}"))
+        Err(error) => {
+            report_error(&error);
+            Some(format!("ERROR: {error:?}"))
+        }
     }
 });
 
 byond_fn!(fn csprng_chacha20(format, n_bytes) {
     let n_bytes: usize = match n_bytes.parse() {
         Ok(n) => n,
-        Err(_) => return Some(String::from("ERROR: Unparseable n_bytes"))
+        Err(error) => {
+            report_failure("parse", error);
+            return Some(String::from("ERROR: Unparseable n_bytes"))
+        }
     };
     if n_bytes < 1 {
+        report_failure("invalid_argument", "Zero bytes not allowed");
         return Some(String::from("ERROR: Zero bytes not allowed"))
     }
     Some(gen_csprng_chacha20(format, n_bytes))
@@ -69,9 +88,13 @@ fn gen_csprng_chacha20(format: &str, n_bytes: usize) -> String {
 byond_fn!(fn prng_chacha20_seeded(format, n_bytes, seed) {
     let n_bytes: usize = match n_bytes.parse() {
         Ok(n) => n,
-        Err(_) => return Some(String::from("ERROR: Unparseable n_bytes"))
+        Err(error) => {
+            report_failure("parse", error);
+            return Some(String::from("ERROR: Unparseable n_bytes"))
+        }
     };
     if n_bytes < 1 {
+        report_failure("invalid_argument", "Zero bytes not allowed");
         return Some(String::from("ERROR: Zero bytes not allowed"))
     }
     Some(gen_prng_chacha20_seeded(format, n_bytes, seed))
@@ -113,18 +136,27 @@ fn format_rng<T: Rng>(rng: &mut T, format: &str, n_bytes: usize) -> String {
             rng.fill_bytes(&mut bytes);
             base64::prelude::BASE64_STANDARD.encode(bytes)
         }
-        _ => String::from("ERROR: Invalid format"),
+        _ => {
+            report_failure("invalid_argument", "Invalid format");
+            String::from("ERROR: Invalid format")
+        }
     }
 }
 
 byond_fn!(fn generate_totp_tolerance(algorithm, base32_seed, tolerance) {
     let tolerance_value: i32 = match tolerance.parse() {
         Ok(value) => value,
-        Err(_) => return Some(String::from("ERROR: Tolerance not a valid integer"))
+        Err(error) => {
+            report_failure("parse", error);
+            return Some(String::from("ERROR: Tolerance not a valid integer"))
+        }
     };
     match totp_generate_tolerance(algorithm, base32_seed, tolerance_value, TOTP_DIGITS, None) {
         Ok(value) => Some(value),
-        Err(error) => Some(format!("ERROR: {error:?}"))
+        Err(error) => {
+            report_error(&error);
+            Some(format!("ERROR: {error:?}"))
+        }
     }
 });
 
@@ -139,6 +171,7 @@ const BUFFER_SIZE: usize = 65536;
 thread_local!( static FILE_HASH_BUFFER: RefCell<[u8; BUFFER_SIZE]> = const { RefCell::new([0; BUFFER_SIZE]) } );
 
 pub fn file_hash(algorithm: &str, path: &str) -> Result<String> {
+    check_path(path)?;
     let mut hasher = HashDispatcher::new(algorithm)?;
     let mut file = File::open(path)?;
 
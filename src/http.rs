@@ -1,8 +1,13 @@
-use crate::{error::Result, jobs};
+use crate::{
+    byond::report_error,
+    error::{Error, Result},
+    jobs::{self, CancelToken, CancellableReader, JobKind, ProgressReader},
+    sandbox::check_path,
+};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap};
-use std::io::Write;
+use std::io::{Read, Write};
 use std::time::Duration;
 
 // ----------------------------------------------------------------------------
@@ -30,12 +35,18 @@ struct Response<'a> {
 byond_fn!(fn http_request_blocking(method, url, body, headers, options) {
     let req = match construct_request(method, url, body, headers, options) {
         Ok(r) => r,
-        Err(e) => return Some(e.to_string())
+        Err(e) => {
+            report_error(&e);
+            return Some(e.to_string())
+        }
     };
 
     match submit_request(req) {
         Ok(r) => Some(r),
-        Err(e) => Some(e.to_string())
+        Err(e) => {
+            report_error(&e);
+            Some(e.to_string())
+        }
     }
 });
 
@@ -43,10 +54,16 @@ byond_fn!(fn http_request_blocking(method, url, body, headers, options) {
 byond_fn!(fn http_request_async(method, url, body, headers, options) {
     let req = match construct_request(method, url, body, headers, options) {
         Ok(r) => r,
-        Err(e) => return Some(e.to_string())
+        Err(e) => {
+            report_error(&e);
+            return Some(e.to_string())
+        }
     };
 
-    Some(jobs::start(move || {
+    // The job's deadline is a little later than the request's own timeout, so
+    // callers still get ureq's timeout error rather than JOB TIMED OUT.
+    let deadline = req.timeout.map(|timeout| timeout + JOB_DEADLINE_MARGIN);
+    Some(jobs::start_with_timeout(JobKind::Http, deadline, move || {
         match submit_request(req) {
             Ok(r) => r,
             Err(e) => e.to_string()
@@ -57,11 +74,14 @@ byond_fn!(fn http_request_async(method, url, body, headers, options) {
 byond_fn!(fn http_request_fire_and_forget(method, url, body, headers, options) {
     let req = match construct_request(method, url, body, headers, options) {
         Ok(r) => r,
-        Err(e) => return Some(e.to_string())
+        Err(e) => {
+            report_error(&e);
+            return Some(e.to_string())
+        }
     };
 
-    std::thread::spawn(move || {
-        let _ = req.req.send_bytes(&req.body); // discard result
+    jobs::spawn(JobKind::Http, move || {
+        let _ = req.send(&jobs::current()); // discard result
     });
     Some("ok".to_owned())
 });
@@ -78,7 +98,24 @@ byond_fn!(fn http_check_request(id) {
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const PKG_NAME: &str = env!("CARGO_PKG_NAME");
 
-pub static HTTP_CLIENT: Lazy<ureq::Agent> = Lazy::new(ureq::agent);
+/// Connecting gives up after this long, however long the request may take.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Waiting for the headers, or for the next part of the body, gives up after
+/// this long. Cancelled jobs only notice between reads, so this also bounds
+/// how long they keep their connection open.
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+/// How much longer than the request's own timeout an async request's job has.
+const JOB_DEADLINE_MARGIN: Duration = Duration::from_secs(5);
+/// The most `http_request_*` reads into a response body, which is also the
+/// limit of ureq's `into_string`.
+const MAX_BODY_BYTES: u64 = 10 * 1024 * 1024;
+
+pub static HTTP_CLIENT: Lazy<ureq::Agent> = Lazy::new(|| {
+    ureq::AgentBuilder::new()
+        .timeout_connect(CONNECT_TIMEOUT)
+        .timeout_read(READ_TIMEOUT)
+        .build()
+});
 
 // ----------------------------------------------------------------------------
 // Request construction and execution
@@ -87,6 +124,26 @@ struct RequestPrep {
     req: ureq::Request,
     output_filename: Option<String>,
     body: Vec<u8>,
+    timeout: Option<Duration>,
+}
+
+impl RequestPrep {
+    /// Sends the request, giving up at whichever comes first of the request's
+    /// own timeout and the deadline of the job it runs in.
+    fn send(self, token: &CancelToken) -> Result<ureq::Response> {
+        if token.is_cancelled() {
+            return Err(Error::JobCancelled);
+        }
+        let timeout = match (self.timeout, token.remaining()) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let req = match timeout {
+            Some(timeout) => self.req.timeout(timeout),
+            None => self.req,
+        };
+        Ok(req.send_bytes(&self.body).map_err(Box::new)?)
+    }
 }
 
 fn construct_request(
@@ -116,15 +173,20 @@ fn construct_request(
     }
 
     let mut output_filename = None;
+    let mut timeout = None;
     if !options.is_empty() {
         let options: RequestOptions = serde_json::from_str(options)?;
+        if let Some(fname) = &options.output_filename {
+            check_path(fname)?;
+        }
         output_filename = options.output_filename;
         if let Some(fname) = options.body_filename {
+            check_path(&fname)?;
             final_body = std::fs::read(fname)?;
         }
 
         if let Some(timeout_seconds) = options.timeout_seconds {
-            req = req.timeout(Duration::from_secs(timeout_seconds));
+            timeout = Some(Duration::from_secs(timeout_seconds));
         }
     }
 
@@ -132,11 +194,14 @@ fn construct_request(
         req,
         output_filename,
         body: final_body,
+        timeout,
     })
 }
 
 fn submit_request(prep: RequestPrep) -> Result<String> {
-    let response = prep.req.send_bytes(&prep.body).map_err(Box::new)?;
+    let token = jobs::current();
+    let output_filename = prep.output_filename.clone();
+    let response = prep.send(&token)?;
 
     let body;
     let mut resp = Response {
@@ -153,14 +218,38 @@ fn submit_request(prep: RequestPrep) -> Result<String> {
         resp.headers.insert(key, value.to_owned());
     }
 
-    if let Some(output_filename) = prep.output_filename {
+    // Bodies are read through the job's cancel token, so a cancelled request
+    // stops reading and closes its connection.
+    if let Some(output_filename) = output_filename {
         let mut writer = std::io::BufWriter::new(std::fs::File::create(output_filename)?);
-        std::io::copy(&mut response.into_reader(), &mut writer)?;
+        let progress = jobs::progress();
+        progress.begin("bytes", content_length(&response));
+        let mut reader = ProgressReader::new(
+            CancellableReader::new(response.into_reader(), token),
+            progress,
+        );
+        std::io::copy(&mut reader, &mut writer)?;
         writer.flush()?;
     } else {
-        body = response.into_string()?;
+        let mut text = String::new();
+        CancellableReader::new(response.into_reader(), token)
+            .take(MAX_BODY_BYTES + 1)
+            .read_to_string(&mut text)?;
+        if text.len() as u64 > MAX_BODY_BYTES {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "response too big for into_string",
+            )
+            .into());
+        }
+        body = text;
         resp.body = Some(&body);
     }
 
     Ok(serde_json::to_string(&resp)?)
 }
+
+/// The response's declared body size, if it sent a usable Content-Length.
+pub fn content_length(response: &ureq::Response) -> Option<u64> {
+    response.header("Content-Length")?.parse().ok()
+}
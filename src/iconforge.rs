@@ -1,5 +1,10 @@
-use crate::{byond::catch_panic, jobs};
+use crate::{
+    byond::{catch_panic, report_error, report_failure},
+    error::Error,
+    jobs::{self, JobKind},
+};
 use iconforge::core::{gags, image_cache, spritesheet, spritesheet::SpritesheetResult};
+use std::fmt::Display;
 
 byond_fn!(fn iconforge_check(id) {
     Some(jobs::check(id))
@@ -25,13 +30,7 @@ byond_fn!(fn iconforge_generate(file_path, spritesheet_name, sprites, hash_icons
     let hash_icons = hash_icons.to_owned();
     let generate_dmi = generate_dmi.to_owned();
     let flatten = flatten.to_owned();
-    Some(match catch_panic(|| spritesheet::spritesheet_multisize_from_universal_icons_str(&file_path, &spritesheet_name, &sprites, &hash_icons, &generate_dmi, &flatten)) {
-        Ok(o) => match o {
-            Ok(o) => o,
-            Err(e) => e.to_string()
-        },
-        Err(e) => e.to_string()
-    })
+    Some(reported_output(catch_panic(|| spritesheet::spritesheet_multisize_from_universal_icons_str(&file_path, &spritesheet_name, &sprites, &hash_icons, &generate_dmi, &flatten))))
 });
 
 byond_fn!(fn iconforge_generate_async(file_path, spritesheet_name, sprites, hash_icons, generate_dmi, flatten) {
@@ -41,14 +40,8 @@ byond_fn!(fn iconforge_generate_async(file_path, spritesheet_name, sprites, hash
     let hash_icons = hash_icons.to_owned();
     let generate_dmi = generate_dmi.to_owned();
     let flatten = flatten.to_owned();
-    Some(jobs::start(move || {
-        match catch_panic(|| spritesheet::spritesheet_multisize_from_universal_icons_str(&file_path, &spritesheet_name, &sprites, &hash_icons, &generate_dmi, &flatten)) {
-            Ok(o) => match o {
-                Ok(o) => o,
-                Err(e) => e.to_string()
-            },
-            Err(e) => e.to_string()
-        }
+    Some(jobs::start(JobKind::Iconforge, move || {
+        output(catch_panic(|| spritesheet::spritesheet_multisize_from_universal_icons_str(&file_path, &spritesheet_name, &sprites, &hash_icons, &generate_dmi, &flatten)))
     }))
 });
 
@@ -57,15 +50,20 @@ byond_fn!(fn iconforge_generate_headless(file_path, sprites, flatten) {
     let sprites = sprites.to_owned();
     let flatten = flatten.to_owned();
     Some(match catch_panic::<_, SpritesheetResult>(|| spritesheet::spritesheet_from_universal_icons_str(&file_path, &sprites, &flatten)) {
-        Ok(o) => match serde_json::to_string::<SpritesheetResult>(&o) {
-            Ok(o) => o,
-            Err(_) => String::from("{\"error\":\"Serde serialization error\"}") // nigh impossible but whatever
+        Ok(o) => {
+            if let Some(error) = &o.error {
+                report_failure("iconforge", error);
+            }
+            match serde_json::to_string::<SpritesheetResult>(&o) {
+                Ok(o) => o,
+                Err(_) => String::from("{\"error\":\"Serde serialization error\"}") // nigh impossible but whatever
+            }
         },
         Err(e) => match serde_json::to_string::<SpritesheetResult>(&SpritesheetResult {
+            error: Some(report_panic(e)),
             file_path: None,
             width: None,
             height: None,
-            error: Some(e.to_string()),
         }) {
             Ok(o) => o,
             Err(_) => String::from("{\"error\":\"Serde serialization error\"}")
@@ -77,27 +75,15 @@ byond_fn!(fn iconforge_cache_valid(input_hash, dmi_hashes, sprites) {
     let input_hash = input_hash.to_owned();
     let dmi_hashes = dmi_hashes.to_owned();
     let sprites = sprites.to_owned();
-    Some(match catch_panic(|| spritesheet::cache_valid(&input_hash, &dmi_hashes, &sprites)) {
-        Ok(o) => match o {
-            Ok(o) => o,
-            Err(e) => e.to_string()
-        },
-        Err(e) => e.to_string()
-    })
+    Some(reported_output(catch_panic(|| spritesheet::cache_valid(&input_hash, &dmi_hashes, &sprites))))
 });
 
 byond_fn!(fn iconforge_cache_valid_async(input_hash, dmi_hashes, sprites) {
     let input_hash = input_hash.to_owned();
     let dmi_hashes = dmi_hashes.to_owned();
     let sprites = sprites.to_owned();
-    Some(jobs::start(move || {
-        match catch_panic(|| spritesheet::cache_valid(&input_hash, &dmi_hashes, &sprites)) {
-            Ok(o) => match o {
-                Ok(o) => o,
-                Err(e) => e.to_string()
-            },
-            Err(e) => e.to_string()
-        }
+    Some(jobs::start(JobKind::Iconforge, move || {
+        output(catch_panic(|| spritesheet::cache_valid(&input_hash, &dmi_hashes, &sprites)))
     }))
 });
 
@@ -105,27 +91,15 @@ byond_fn!(fn iconforge_load_gags_config(config_path, config_json, config_icon_pa
     let config_path = config_path.to_owned();
     let config_json = config_json.to_owned();
     let config_icon_path = config_icon_path.to_owned();
-    Some(match catch_panic(|| gags::load_gags_config(&config_path, &config_json, &config_icon_path)) {
-        Ok(o) => match o {
-            Ok(o) => o,
-            Err(e) => e.to_string()
-        },
-        Err(e) => e.to_string()
-    })
+    Some(reported_output(catch_panic(|| gags::load_gags_config(&config_path, &config_json, &config_icon_path))))
 });
 
 byond_fn!(fn iconforge_load_gags_config_async(config_path, config_json, config_icon_path) {
     let config_path = config_path.to_owned();
     let config_json = config_json.to_owned();
     let config_icon_path = config_icon_path.to_owned();
-    Some(jobs::start(move || {
-        match catch_panic(|| gags::load_gags_config(&config_path, &config_json, &config_icon_path)) {
-            Ok(o) => match o {
-                Ok(o) => o,
-                Err(e) => e.to_string()
-            },
-            Err(e) => e.to_string()
-        }
+    Some(jobs::start(JobKind::Iconforge, move || {
+        output(catch_panic(|| gags::load_gags_config(&config_path, &config_json, &config_icon_path)))
     }))
 });
 
@@ -133,26 +107,42 @@ byond_fn!(fn iconforge_gags(config_path, colors, output_dmi_path) {
     let config_path = config_path.to_owned();
     let colors = colors.to_owned();
     let output_dmi_path = output_dmi_path.to_owned();
-    Some(match catch_panic(|| gags::gags(&config_path, &colors, &output_dmi_path)) {
-        Ok(o) => match o {
-            Ok(o) => o,
-            Err(e) => e.to_string()
-        },
-        Err(e) => e.to_string()
-    })
+    Some(reported_output(catch_panic(|| gags::gags(&config_path, &colors, &output_dmi_path))))
 });
 
 byond_fn!(fn iconforge_gags_async(config_path, colors, output_dmi_path) {
     let config_path = config_path.to_owned();
     let colors = colors.to_owned();
     let output_dmi_path = output_dmi_path.to_owned();
-    Some(jobs::start(move || {
-        match catch_panic(|| gags::gags(&config_path, &colors, &output_dmi_path)) {
-            Ok(o) => match o {
-                Ok(o) => o,
-                Err(e) => e.to_string()
-            },
-            Err(e) => e.to_string()
-        }
+    Some(jobs::start(JobKind::Iconforge, move || {
+        output(catch_panic(|| gags::gags(&config_path, &colors, &output_dmi_path)))
     }))
 });
+
+/// The string DM gets back from an iconforge call, which is the error message
+/// if it failed or panicked.
+fn output<E: Display>(result: Result<Result<String, E>, Error>) -> String {
+    match result {
+        Ok(Ok(o)) => o,
+        Ok(Err(e)) => e.to_string(),
+        Err(e) => e.to_string(),
+    }
+}
+
+/// Like `output`, also reporting a failure for the result envelope. Jobs use
+/// `output`, as their results are never wrapped.
+fn reported_output<E: Display>(result: Result<Result<String, E>, Error>) -> String {
+    match result {
+        Ok(Ok(o)) => o,
+        Ok(Err(e)) => {
+            report_failure("iconforge", &e);
+            e.to_string()
+        }
+        Err(e) => report_panic(e),
+    }
+}
+
+fn report_panic(error: Error) -> String {
+    report_error(&error);
+    error.to_string()
+}
@@ -1,22 +1,333 @@
 //! Job system
+use crate::error::{Error, Result};
 use flume::Receiver;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde_json::json;
 use std::{
     cell::RefCell,
-    collections::hash_map::{Entry, HashMap},
+    collections::{
+        BTreeMap, VecDeque,
+        hash_map::{Entry, HashMap},
+    },
+    io::{self, Read},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        Arc, Condvar, Mutex, MutexGuard,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
     thread,
+    time::{Duration, Instant},
 };
 
 struct Job {
     rx: Receiver<Output>,
-    handle: thread::JoinHandle<()>,
+    token: CancelToken,
+    progress: ProgressHandle,
+    kind: JobKind,
+    started: Instant,
+}
+
+impl Job {
+    /// The job's final output, or `None` if it is still pending.
+    fn finished(&self) -> Option<Output> {
+        match self.rx.try_recv() {
+            Ok(result) => Some(result),
+            Err(_) if self.token.timed_out() => {
+                record_timeout(self.kind, &self.token);
+                self.token.cancel();
+                Some(JOB_TIMED_OUT.to_owned())
+            }
+            Err(flume::TryRecvError::Disconnected) => Some(JOB_PANICKED.to_owned()),
+            Err(flume::TryRecvError::Empty) => None,
+        }
+    }
+
+    fn is_pending(&self) -> bool {
+        self.rx.is_empty() && !self.rx.is_disconnected()
+    }
 }
 
 type Output = String;
 type JobId = String;
+type Task = Box<dyn FnOnce() + Send + 'static>;
 
 const NO_RESULTS_YET: &str = "NO RESULTS YET";
 const NO_SUCH_JOB: &str = "NO SUCH JOB";
 const JOB_PANICKED: &str = "JOB PANICKED";
+const JOB_TIMED_OUT: &str = "JOB TIMED OUT";
+
+const DEFAULT_WORKERS: usize = 16;
+const MAX_WORKERS: usize = 256;
+
+/// The subsystem a job belongs to. Every kind gets its own queue, and workers
+/// take from the queues in turn so a burst of one kind cannot starve the rest.
+#[allow(dead_code)] // Used depending on feature set
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JobKind {
+    Http,
+    Sql,
+    Iconforge,
+    Unzip,
+    File,
+    Compress,
+}
+
+impl JobKind {
+    const COUNT: usize = 6;
+    const ALL: [JobKind; Self::COUNT] = [
+        Self::Http,
+        Self::Sql,
+        Self::Iconforge,
+        Self::Unzip,
+        Self::File,
+        Self::Compress,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Http => "http",
+            Self::Sql => "sql",
+            Self::Iconforge => "iconforge",
+            Self::Unzip => "unzip",
+            Self::File => "file",
+            Self::Compress => "compress",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Cancellation
+
+type CancelHook = Box<dyn FnOnce() + Send + 'static>;
+
+#[derive(Default)]
+struct CancelState {
+    cancelled: AtomicBool,
+    /// Set once the job has been counted as cancelled or timed out.
+    abandoned: AtomicBool,
+    deadline: Option<Instant>,
+    hooks: Mutex<BTreeMap<usize, CancelHook>>,
+    #[allow(dead_code)] // Used depending on feature set
+    next_hook: AtomicUsize,
+}
+
+/// Shared between a job and the BYOND side. Jobs are expected to poll
+/// `is_cancelled` at convenient points and give up early when it is set.
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<CancelState>);
+
+impl CancelToken {
+    fn new(timeout: Option<Duration>) -> Self {
+        Self(Arc::new(CancelState {
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
+            ..Default::default()
+        }))
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::Acquire) || self.timed_out()
+    }
+
+    /// Marks the job as given up on, returning whether this was the first
+    /// time, so each abandoned job is counted once in the stats.
+    fn abandon(&self) -> bool {
+        !self.0.abandoned.swap(true, Ordering::AcqRel)
+    }
+
+    fn timed_out(&self) -> bool {
+        self.0
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Time left before the job's deadline, if it has one.
+    #[allow(dead_code)] // Used depending on feature set
+    pub fn remaining(&self) -> Option<Duration> {
+        self.0
+            .deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Registers `hook` to be run on its own thread if the job is cancelled
+    /// while the returned guard is alive. Used to interrupt blocking calls
+    /// that cannot poll `is_cancelled` themselves.
+    #[allow(dead_code)] // Used depending on feature set
+    pub fn on_cancel<F: FnOnce() + Send + 'static>(&self, hook: F) -> CancelHookGuard {
+        let mut hooks = self.0.hooks.lock().unwrap_or_else(|e| e.into_inner());
+        if self.0.cancelled.load(Ordering::Acquire) {
+            drop(hooks);
+            thread::spawn(hook);
+            return CancelHookGuard(None);
+        }
+        let id = self.0.next_hook.fetch_add(1, Ordering::Relaxed);
+        hooks.insert(id, Box::new(hook));
+        CancelHookGuard(Some((self.clone(), id)))
+    }
+
+    fn cancel(&self) {
+        let mut hooks = self.0.hooks.lock().unwrap_or_else(|e| e.into_inner());
+        if self.0.cancelled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let hooks = std::mem::take(&mut *hooks);
+        if !hooks.is_empty() {
+            thread::spawn(move || hooks.into_values().for_each(|hook| hook()));
+        }
+    }
+}
+
+/// Wraps a reader so it fails once the job is cancelled, letting long
+/// downloads bail out and close their connection.
+#[allow(dead_code)] // Used depending on feature set
+pub struct CancellableReader<R> {
+    inner: R,
+    token: CancelToken,
+}
+
+#[allow(dead_code)] // Used depending on feature set
+impl<R> CancellableReader<R> {
+    pub fn new(inner: R, token: CancelToken) -> Self {
+        Self { inner, token }
+    }
+}
+
+impl<R: Read> Read for CancellableReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.token.is_cancelled() {
+            return Err(io::Error::other(Error::JobCancelled.to_string()));
+        }
+        self.inner.read(buf)
+    }
+}
+
+/// Disarms a hook registered with `CancelToken::on_cancel` when dropped.
+#[allow(dead_code)] // Used depending on feature set
+pub struct CancelHookGuard(Option<(CancelToken, usize)>);
+
+impl Drop for CancelHookGuard {
+    fn drop(&mut self) {
+        if let Some((token, id)) = self.0.take() {
+            let mut hooks = token.0.hooks.lock().unwrap_or_else(|e| e.into_inner());
+            hooks.remove(&id);
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Progress
+
+/// How far along a job is, in whatever unit it counts. `total` is `None` when
+/// the job cannot know it in advance.
+#[derive(Clone, Copy, Default)]
+struct Progress {
+    done: u64,
+    total: Option<u64>,
+    unit: Option<&'static str>,
+}
+
+/// Lets a job publish its progress, which DM can read while it is still running.
+#[derive(Clone, Default)]
+pub struct ProgressHandle(Arc<Mutex<Progress>>);
+
+impl ProgressHandle {
+    fn lock(&self) -> MutexGuard<'_, Progress> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Starts counting from zero in a new unit, e.g. when a job moves from
+    /// downloading bytes to extracting files.
+    #[allow(dead_code)] // Used depending on feature set
+    pub fn begin(&self, unit: &'static str, total: Option<u64>) {
+        *self.lock() = Progress {
+            done: 0,
+            total,
+            unit: Some(unit),
+        };
+    }
+
+    #[allow(dead_code)] // Used depending on feature set
+    pub fn advance(&self, amount: u64) {
+        self.lock().done += amount;
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let progress = *self.lock();
+        json!({
+            "done": progress.done,
+            "total": progress.total,
+            "unit": progress.unit,
+        })
+    }
+}
+
+/// Wraps a reader so every byte read counts towards the job's progress.
+#[allow(dead_code)] // Used depending on feature set
+pub struct ProgressReader<R> {
+    inner: R,
+    progress: ProgressHandle,
+}
+
+#[allow(dead_code)] // Used depending on feature set
+impl<R> ProgressReader<R> {
+    pub fn new(inner: R, progress: ProgressHandle) -> Self {
+        Self { inner, progress }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.progress.advance(read as u64);
+        Ok(read)
+    }
+}
+
+thread_local! {
+    static CURRENT_TOKEN: RefCell<CancelToken> = RefCell::default();
+    static CURRENT_PROGRESS: RefCell<ProgressHandle> = RefCell::default();
+}
+
+/// Makes a job's token and progress handle current on this thread until
+/// dropped, so a panicking job can't leave them behind for the next task.
+struct CurrentJob;
+
+impl CurrentJob {
+    fn enter(token: CancelToken, progress: ProgressHandle) -> Self {
+        CURRENT_TOKEN.with(|current| current.replace(token));
+        CURRENT_PROGRESS.with(|current| current.replace(progress));
+        Self
+    }
+}
+
+impl Drop for CurrentJob {
+    fn drop(&mut self) {
+        CURRENT_TOKEN.with(|current| current.take());
+        CURRENT_PROGRESS.with(|current| current.take());
+    }
+}
+
+/// The cancellation token of the job running on this thread. Outside of a job
+/// this is a token which is never cancelled, so blocking calls can share code
+/// with their async counterparts.
+#[allow(dead_code)] // Used depending on feature set
+pub fn current() -> CancelToken {
+    CURRENT_TOKEN.with(|token| token.borrow().clone())
+}
+
+/// The progress handle of the job running on this thread. Outside of a job,
+/// reports go nowhere.
+#[allow(dead_code)] // Used depending on feature set
+pub fn progress() -> ProgressHandle {
+    CURRENT_PROGRESS.with(|progress| progress.borrow().clone())
+}
+
+// ----------------------------------------------------------------------------
+// Job tracking
 
 #[derive(Default)]
 struct Jobs {
@@ -25,14 +336,63 @@ struct Jobs {
 }
 
 impl Jobs {
-    fn start<F: FnOnce() -> Output + Send + 'static>(&mut self, f: F) -> JobId {
+    fn start<F: FnOnce() -> Output + Send + 'static>(
+        &mut self,
+        kind: JobKind,
+        timeout: Option<Duration>,
+        f: F,
+    ) -> JobId {
         let (tx, rx) = flume::unbounded();
-        let handle = thread::spawn(move || {
-            let _ = tx.send(f());
-        });
+        let token = CancelToken::new(timeout.or_else(|| POOL.default_timeout(kind)));
+        let job_token = token.clone();
+        let progress = ProgressHandle::default();
+        let job_progress = progress.clone();
+        let started = Instant::now();
+        POOL.submit(
+            kind,
+            Box::new(move || {
+                // Don't bother starting jobs which were abandoned while queued.
+                if job_token.is_cancelled() {
+                    record_timeout(kind, &job_token);
+                    return;
+                }
+                let dequeued = Instant::now();
+                let result = {
+                    let _current = CurrentJob::enter(job_token.clone(), job_progress);
+                    panic::catch_unwind(AssertUnwindSafe(f))
+                };
+                let finished = Instant::now();
+                // Whatever a job does after being abandoned is just its reaction to
+                // that, and it was already counted as cancelled or timed out.
+                if job_token.is_cancelled() {
+                    record_timeout(kind, &job_token);
+                    return;
+                }
+                let Ok(result) = result else {
+                    record(kind, |stats| stats.panicked += 1);
+                    return;
+                };
+                record(kind, |stats| {
+                    stats.completed += 1;
+                    stats.total_queued += dequeued - started;
+                    stats.total_latency += finished - started;
+                    stats.max_latency = stats.max_latency.max(finished - started);
+                });
+                let _ = tx.send(result);
+            }),
+        );
         let id = self.next_job.to_string();
         self.next_job += 1;
-        self.map.insert(id.clone(), Job { rx, handle });
+        self.map.insert(
+            id.clone(),
+            Job {
+                rx,
+                token,
+                progress,
+                kind,
+                started,
+            },
+        );
         id
     }
 
@@ -41,13 +401,105 @@ impl Jobs {
             Entry::Occupied(occupied) => occupied,
             Entry::Vacant(_) => return NO_SUCH_JOB.to_owned(),
         };
-        let result = match entry.get().rx.try_recv() {
-            Ok(result) => result,
-            Err(flume::TryRecvError::Disconnected) => JOB_PANICKED.to_owned(),
-            Err(flume::TryRecvError::Empty) => return NO_RESULTS_YET.to_owned(),
-        };
-        let _ = entry.remove().handle.join();
-        result
+        match entry.get().finished() {
+            Some(result) => {
+                entry.remove();
+                result
+            }
+            None => NO_RESULTS_YET.to_owned(),
+        }
+    }
+
+    fn poll_completed(&mut self) -> serde_json::Map<String, serde_json::Value> {
+        let mut completed = serde_json::Map::new();
+        self.map.retain(|id, job| match job.finished() {
+            Some(result) => {
+                completed.insert(id.clone(), result.into());
+                false
+            }
+            None => true,
+        });
+        completed
+    }
+
+    fn progress(&self, id: &str) -> Output {
+        match self.map.get(id) {
+            Some(job) => job.progress.to_json().to_string(),
+            None => NO_SUCH_JOB.to_owned(),
+        }
+    }
+
+    fn cancel(&mut self, id: &str) -> bool {
+        match self.map.remove(id) {
+            Some(job) => {
+                if job.token.abandon() {
+                    record(job.kind, |stats| stats.cancelled += 1);
+                }
+                job.token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn stats(&self) -> serde_json::Value {
+        // Count jobs past their deadline even if nobody has checked them yet.
+        for job in self.map.values() {
+            if job.token.timed_out() {
+                record_timeout(job.kind, &job.token);
+            }
+        }
+
+        let now = Instant::now();
+        let queued = POOL.queued();
+        let stats = STATS.lock().unwrap_or_else(|e| e.into_inner());
+        let elapsed = now.duration_since(stats.since).as_secs_f64();
+
+        let mut kinds = serde_json::Map::new();
+        for kind in JobKind::ALL {
+            let pending: Vec<&Job> = self
+                .map
+                .values()
+                .filter(|job| job.kind == kind && job.is_pending())
+                .collect();
+            let oldest_pending = pending
+                .iter()
+                .map(|job| now.duration_since(job.started))
+                .max()
+                .unwrap_or_default();
+            let kind_stats = &stats.kinds[kind as usize];
+            let completed = kind_stats.completed;
+            let mean = |total: Duration| match completed {
+                0 => 0.0,
+                n => millis(total) / n as f64,
+            };
+            let throughput = match elapsed {
+                0.0 => 0.0,
+                elapsed => completed as f64 / elapsed,
+            };
+            kinds.insert(
+                kind.name().to_owned(),
+                json!({
+                    "pending": pending.len(),
+                    "queued": queued[kind as usize],
+                    "oldest_pending_seconds": oldest_pending.as_secs_f64(),
+                    "completed": completed,
+                    "panicked": kind_stats.panicked,
+                    "timed_out": kind_stats.timed_out,
+                    "cancelled": kind_stats.cancelled,
+                    "throughput_per_second": throughput,
+                    "mean_queue_ms": mean(kind_stats.total_queued),
+                    "mean_latency_ms": mean(kind_stats.total_latency),
+                    "max_latency_ms": millis(kind_stats.max_latency),
+                }),
+            );
+        }
+
+        json!({
+            "workers": POOL.workers(),
+            "uptime_seconds": elapsed,
+            "kinds": kinds,
+        })
     }
 }
 
@@ -55,10 +507,479 @@ thread_local! {
     static JOBS: RefCell<Jobs> = RefCell::default();
 }
 
-pub fn start<F: FnOnce() -> Output + Send + 'static>(f: F) -> JobId {
-    JOBS.with(|jobs| jobs.borrow_mut().start(f))
+#[allow(dead_code)] // Used depending on feature set
+pub fn start<F: FnOnce() -> Output + Send + 'static>(kind: JobKind, f: F) -> JobId {
+    start_with_timeout(kind, None, f)
+}
+
+/// Like `start`, but the job times out after `timeout` instead of its kind's
+/// default deadline.
+pub fn start_with_timeout<F: FnOnce() -> Output + Send + 'static>(
+    kind: JobKind,
+    timeout: Option<Duration>,
+    f: F,
+) -> JobId {
+    JOBS.with(|jobs| jobs.borrow_mut().start(kind, timeout, f))
+}
+
+/// Converts a deadline in seconds from DM. Zero or less means no deadline.
+pub fn timeout_from_secs(seconds: f32) -> Result<Option<Duration>> {
+    if seconds <= 0.0 {
+        return Ok(None);
+    }
+    Duration::try_from_secs_f32(seconds)
+        .map(Some)
+        .map_err(|_| Error::InvalidTimeout(seconds))
 }
 
 pub fn check(id: &str) -> String {
     JOBS.with(|jobs| jobs.borrow_mut().check(id))
 }
+
+byond_fn!(
+    fn jobs_poll_completed() {
+        let completed = JOBS.with(|jobs| jobs.borrow_mut().poll_completed());
+        Some(serde_json::Value::Object(completed).to_string())
+    }
+);
+
+byond_fn!(
+    fn jobs_stats() {
+        Some(JOBS.with(|jobs| jobs.borrow().stats()).to_string())
+    }
+);
+
+byond_fn!(fn job_progress(id) {
+    Some(JOBS.with(|jobs| jobs.borrow().progress(id)))
+});
+
+byond_fn!(fn job_cancel(id) {
+    Some(JOBS.with(|jobs| jobs.borrow_mut().cancel(id)).to_string())
+});
+
+/// Runs `f` on the worker pool without tracking a result.
+#[allow(dead_code)] // Used depending on feature set
+pub fn spawn<F: FnOnce() + Send + 'static>(kind: JobKind, f: F) {
+    POOL.submit(kind, Box::new(f));
+}
+
+// ----------------------------------------------------------------------------
+// Statistics
+
+/// Totals for one kind of job, kept since the library was first used.
+#[derive(Default)]
+struct KindStats {
+    completed: u64,
+    panicked: u64,
+    timed_out: u64,
+    cancelled: u64,
+    total_queued: Duration,
+    total_latency: Duration,
+    max_latency: Duration,
+}
+
+struct Stats {
+    since: Instant,
+    kinds: [KindStats; JobKind::COUNT],
+}
+
+static STATS: Lazy<Mutex<Stats>> = Lazy::new(|| {
+    Mutex::new(Stats {
+        since: Instant::now(),
+        kinds: Default::default(),
+    })
+});
+
+fn record(kind: JobKind, f: impl FnOnce(&mut KindStats)) {
+    f(&mut STATS.lock().unwrap_or_else(|e| e.into_inner()).kinds[kind as usize]);
+}
+
+/// Counts a job as timed out if it is past its deadline and wasn't already
+/// counted as cancelled or timed out.
+fn record_timeout(kind: JobKind, token: &CancelToken) {
+    if token.timed_out() && token.abandon() {
+        record(kind, |stats| stats.timed_out += 1);
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+// ----------------------------------------------------------------------------
+// Worker pool
+
+struct PoolState {
+    queues: [VecDeque<Task>; JobKind::COUNT],
+    next_queue: usize,
+    workers: usize,
+    target_workers: usize,
+    timeouts: [Option<Duration>; JobKind::COUNT],
+}
+
+impl PoolState {
+    fn pop(&mut self) -> Option<Task> {
+        for _ in 0..self.queues.len() {
+            let index = self.next_queue;
+            self.next_queue = (index + 1) % self.queues.len();
+            if let Some(task) = self.queues[index].pop_front() {
+                return Some(task);
+            }
+        }
+        None
+    }
+}
+
+struct Pool {
+    state: Mutex<PoolState>,
+    work_available: Condvar,
+}
+
+static POOL: Lazy<Pool> = Lazy::new(|| Pool {
+    state: Mutex::new(PoolState {
+        queues: Default::default(),
+        next_queue: 0,
+        workers: 0,
+        target_workers: DEFAULT_WORKERS,
+        timeouts: Default::default(),
+    }),
+    work_available: Condvar::new(),
+});
+
+impl Pool {
+    fn lock(&self) -> MutexGuard<'_, PoolState> {
+        // Jobs never run while the lock is held, so a poisoned lock is still consistent.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn submit(&'static self, kind: JobKind, task: Task) {
+        let mut state = self.lock();
+        state.queues[kind as usize].push_back(task);
+        // Workers are started lazily, so an idle server holds no threads.
+        if state.workers < state.target_workers {
+            self.spawn_worker(&mut state);
+        }
+        drop(state);
+        self.work_available.notify_one();
+    }
+
+    fn queued(&self) -> [usize; JobKind::COUNT] {
+        self.lock().queues.each_ref().map(VecDeque::len)
+    }
+
+    fn workers(&self) -> usize {
+        self.lock().workers
+    }
+
+    fn default_timeout(&self, kind: JobKind) -> Option<Duration> {
+        self.lock().timeouts[kind as usize]
+    }
+
+    fn set_timeout(&self, kind: JobKind, timeout: Option<Duration>) {
+        self.lock().timeouts[kind as usize] = timeout;
+    }
+
+    fn set_workers(&'static self, count: usize) {
+        let mut state = self.lock();
+        state.target_workers = count;
+        let queued: usize = state.queues.iter().map(VecDeque::len).sum();
+        while state.workers < state.target_workers.min(queued) {
+            self.spawn_worker(&mut state);
+        }
+        drop(state);
+        // Wake everyone so surplus workers notice they should exit.
+        self.work_available.notify_all();
+    }
+
+    fn spawn_worker(&'static self, state: &mut PoolState) {
+        let spawned = thread::Builder::new()
+            .name("rustg-job-worker".to_owned())
+            .spawn(move || self.work());
+        if spawned.is_ok() {
+            state.workers += 1;
+        }
+    }
+
+    fn work(&self) {
+        loop {
+            let task = {
+                let mut state = self.lock();
+                loop {
+                    if state.workers > state.target_workers {
+                        state.workers -= 1;
+                        return;
+                    }
+                    if let Some(task) = state.pop() {
+                        break task;
+                    }
+                    state = self
+                        .work_available
+                        .wait(state)
+                        .unwrap_or_else(|e| e.into_inner());
+                }
+            };
+            // A panicking job drops its sender, which `check` reports as JOB PANICKED.
+            let _ = panic::catch_unwind(AssertUnwindSafe(task));
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Configuration
+
+#[derive(Deserialize)]
+struct PoolOptions {
+    #[serde(default)]
+    workers: Option<usize>,
+    /// Default deadline per job kind, keyed by kind name. Zero removes the deadline.
+    #[serde(default)]
+    timeout_seconds: HashMap<String, f32>,
+}
+
+byond_fn!(fn jobs_configure(options) {
+    configure(options).err()
+});
+
+fn configure(options: &str) -> Result<()> {
+    let options: PoolOptions = serde_json::from_str(options)?;
+    if let Some(workers) = options.workers {
+        POOL.set_workers(workers.clamp(1, MAX_WORKERS));
+    }
+    for (kind, seconds) in options.timeout_seconds {
+        let Some(kind) = JobKind::from_name(&kind) else {
+            return Err(Error::InvalidJobKind(kind));
+        };
+        POOL.set_timeout(kind, timeout_from_secs(seconds)?);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wait_for(id: &str) -> Output {
+        loop {
+            let result = check(id);
+            if result != NO_RESULTS_YET {
+                return result;
+            }
+            thread::yield_now();
+        }
+    }
+
+    /// Polls `condition` until it holds, returning false if it still doesn't
+    /// after a few seconds.
+    fn wait_until(condition: impl Fn() -> bool) -> bool {
+        let started = Instant::now();
+        while !condition() {
+            if started.elapsed() > Duration::from_secs(5) {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        true
+    }
+
+    #[test]
+    fn jobs_complete_on_pool() {
+        let ids: Vec<JobId> = (0..64)
+            .map(|i| start(JobKind::Sql, move || i.to_string()))
+            .collect();
+        for (i, id) in ids.iter().enumerate() {
+            assert_eq!(wait_for(id), i.to_string());
+            assert_eq!(check(id), NO_SUCH_JOB);
+        }
+    }
+
+    #[test]
+    fn panicking_job_does_not_kill_worker() {
+        let id = start(JobKind::Http, || panic!("job panic"));
+        assert_eq!(wait_for(&id), JOB_PANICKED);
+        let id = start(JobKind::Http, || "ok".to_owned());
+        assert_eq!(wait_for(&id), "ok");
+    }
+
+    #[test]
+    fn poll_completed_drains_finished_jobs() {
+        let (tx, rx) = flume::bounded::<()>(0);
+        let blocked = start(JobKind::Sql, move || {
+            let _ = rx.recv();
+            String::new()
+        });
+        let ids: Vec<JobId> = (0..8)
+            .map(|i| start(JobKind::Sql, move || i.to_string()))
+            .collect();
+
+        let mut results = serde_json::Map::new();
+        while ids.iter().any(|id| !results.contains_key(id)) {
+            results.extend(JOBS.with(|jobs| jobs.borrow_mut().poll_completed()));
+            thread::yield_now();
+        }
+        assert!(!results.contains_key(&blocked));
+        for (i, id) in ids.iter().enumerate() {
+            assert_eq!(results[id], i.to_string());
+            assert_eq!(check(id), NO_SUCH_JOB);
+        }
+
+        drop(tx);
+        assert_eq!(wait_for(&blocked), "");
+    }
+
+    #[test]
+    fn stats_report_pending_and_completed() {
+        let (tx, rx) = flume::bounded::<()>(0);
+        let blocked = start(JobKind::Iconforge, move || {
+            let _ = rx.recv();
+            String::new()
+        });
+        let stats = JOBS.with(|jobs| jobs.borrow().stats());
+        assert_eq!(stats["kinds"]["iconforge"]["pending"], 1);
+
+        drop(tx);
+        assert_eq!(wait_for(&blocked), "");
+        let stats = JOBS.with(|jobs| jobs.borrow().stats());
+        assert_eq!(stats["kinds"]["iconforge"]["pending"], 0);
+        assert!(stats["kinds"]["iconforge"]["completed"].as_u64().unwrap() >= 1);
+    }
+
+    #[test]
+    fn progress_is_readable_while_running() {
+        let (tx, rx) = flume::bounded::<()>(0);
+        let (reported_tx, reported_rx) = flume::bounded(1);
+        let id = start(JobKind::Unzip, move || {
+            let mut reader = ProgressReader::new(&b"hello"[..], progress());
+            progress().begin("bytes", Some(5));
+            io::copy(&mut reader, &mut io::sink()).unwrap();
+            let _ = reported_tx.send(());
+            let _ = rx.recv();
+            String::new()
+        });
+        reported_rx.recv().unwrap();
+        let progress: serde_json::Value =
+            serde_json::from_str(&JOBS.with(|jobs| jobs.borrow().progress(&id))).unwrap();
+        assert_eq!(progress, json!({"done": 5, "total": 5, "unit": "bytes"}));
+        assert_eq!(check(&id), NO_RESULTS_YET);
+
+        drop(tx);
+        assert_eq!(wait_for(&id), "");
+    }
+
+    #[test]
+    fn stuck_job_times_out_and_is_cancelled() {
+        let (tx, rx) = flume::bounded(1);
+        let id = JOBS.with(|jobs| {
+            jobs.borrow_mut()
+                .start(JobKind::Unzip, Some(Duration::from_millis(10)), move || {
+                    let token = current();
+                    while !token.is_cancelled() {
+                        thread::yield_now();
+                    }
+                    let _ = tx.send(());
+                    String::new()
+                })
+        });
+        assert_eq!(wait_for(&id), JOB_TIMED_OUT);
+        assert_eq!(check(&id), NO_SUCH_JOB);
+        // Either the job noticed the deadline, or it was skipped before starting.
+        assert!(!matches!(
+            rx.recv_timeout(Duration::from_secs(5)),
+            Err(flume::RecvTimeoutError::Timeout)
+        ));
+    }
+
+    #[test]
+    fn per_job_deadline_overrides_default() {
+        let id = start_with_timeout(JobKind::File, Some(Duration::from_millis(10)), || {
+            let token = current();
+            while !token.is_cancelled() {
+                thread::yield_now();
+            }
+            String::new()
+        });
+        assert_eq!(wait_for(&id), JOB_TIMED_OUT);
+
+        assert_eq!(timeout_from_secs(0.0).unwrap(), None);
+        assert_eq!(
+            timeout_from_secs(1.5).unwrap(),
+            Some(Duration::from_millis(1500))
+        );
+        assert!(timeout_from_secs(f32::INFINITY).is_err());
+        assert!(timeout_from_secs(f32::NAN).is_err());
+    }
+
+    #[test]
+    fn panicking_job_does_not_leave_its_token_behind() {
+        let token = CancelToken::new(Some(Duration::ZERO));
+        let _ = panic::catch_unwind(|| {
+            let _current = CurrentJob::enter(token, ProgressHandle::default());
+            panic!("job panic");
+        });
+        assert!(!current().is_cancelled());
+        assert_eq!(current().remaining(), None);
+    }
+
+    #[test]
+    fn abandoned_jobs_are_not_counted_as_completed() {
+        let kind_stats = || JOBS.with(|jobs| jobs.borrow().stats())["kinds"]["compress"].clone();
+        let before = kind_stats();
+
+        let id = start_with_timeout(
+            JobKind::Compress,
+            Some(Duration::from_millis(10)),
+            move || {
+                let token = current();
+                while !token.is_cancelled() {
+                    thread::yield_now();
+                }
+                String::new()
+            },
+        );
+        // Nobody checks the job, but it still counts as timed out exactly once.
+        let timed_out = before["timed_out"].as_u64().unwrap() + 1;
+        assert!(wait_until(|| kind_stats()["timed_out"] == timed_out));
+        let after = kind_stats();
+        assert_eq!(after["completed"], before["completed"]);
+
+        assert_eq!(wait_for(&id), JOB_TIMED_OUT);
+        assert_eq!(kind_stats()["timed_out"], after["timed_out"]);
+
+        let (tx, rx) = flume::bounded::<()>(0);
+        let id = start(JobKind::Compress, move || {
+            let _ = rx.recv();
+            String::new()
+        });
+        let token = JOBS.with(|jobs| jobs.borrow().map[&id].token.clone());
+        assert!(JOBS.with(|jobs| jobs.borrow_mut().cancel(&id)));
+        drop(tx);
+        // The worker keeps its copy of the token until it is done with the job,
+        // including its stats.
+        assert!(wait_until(|| Arc::strong_count(&token.0) == 1));
+        let cancelled = kind_stats();
+        assert_eq!(
+            cancelled["cancelled"],
+            before["cancelled"].as_u64().unwrap() + 1
+        );
+        assert_eq!(cancelled["completed"], before["completed"]);
+    }
+
+    #[test]
+    fn cancel_runs_hooks() {
+        let (tx, rx) = flume::bounded(1);
+        let (started_tx, started_rx) = flume::bounded(1);
+        let id = start(JobKind::Iconforge, move || {
+            let _guard = current().on_cancel(move || {
+                let _ = tx.send(());
+            });
+            let _ = started_tx.send(());
+            while !current().is_cancelled() {
+                thread::yield_now();
+            }
+            String::new()
+        });
+        started_rx.recv().unwrap();
+        assert!(JOBS.with(|jobs| jobs.borrow_mut().cancel(&id)));
+        assert!(rx.recv_timeout(Duration::from_secs(5)).is_ok());
+        assert_eq!(check(&id), NO_SUCH_JOB);
+    }
+}
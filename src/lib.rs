@@ -8,6 +8,8 @@
 mod byond;
 #[allow(dead_code)]
 mod error;
+mod metrics;
+mod sandbox;
 
 #[cfg(feature = "jobs")]
 mod jobs;
@@ -18,6 +20,10 @@ pub mod acreplace;
 pub mod cave_system_generator;
 #[cfg(feature = "cellularnoise")]
 pub mod cellularnoise;
+#[cfg(feature = "compress")]
+pub mod compress;
+#[cfg(feature = "csv")]
+pub mod csv;
 #[cfg(feature = "dbpnoise")]
 pub mod dbpnoise;
 #[cfg(feature = "dice")]
@@ -28,6 +34,8 @@ pub mod dmi;
 pub mod ed25519;
 #[cfg(feature = "file")]
 pub mod file;
+#[cfg(feature = "file_watch")]
+pub mod file_watch;
 #[cfg(feature = "git")]
 pub mod git;
 #[cfg(feature = "hash")]
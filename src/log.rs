@@ -1,63 +1,834 @@
-use crate::error::Result;
-use chrono::Utc;
+use crate::{
+    byond::{deserialize_byond_bool, report_error},
+    error::{Error, Result},
+    sandbox::check_path,
+};
+use chrono::{
+    DateTime, FixedOffset, Local, SecondsFormat, TimeZone, Utc,
+    format::{Item, StrftimeItems},
+};
+use serde::Deserialize;
+use serde_json::{Map, Value};
 use std::{
     cell::RefCell,
     collections::hash_map::{Entry, HashMap},
     ffi::OsString,
     fs,
     fs::{File, OpenOptions},
-    io::Write,
-    path::Path,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{Receiver, SyncSender, sync_channel},
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
 };
 
+/// Timestamps of `log_write` entries, unless configured otherwise.
+const DEFAULT_TIMESTAMP_FORMAT: &str = "%F %T%.3f";
+/// Keys `log_write_json` fills in itself.
+const RESERVED_JSON_FIELDS: [&str; 3] = ["timestamp", "category", "round_time"];
+/// Rotated logs are renamed to `<name>.<this format>` with timestamp naming,
+/// which sorts oldest first.
+const ROTATED_TIMESTAMP_FORMAT: &str = "%Y%m%d-%H%M%S%3f";
+const ROTATED_TIMESTAMP_LEN: usize = "YYYYmmdd-HHMMSSfff".len();
+/// How many entries the background writer queues before `log_write` waits.
+const DEFAULT_QUEUE_SIZE: usize = 16 * 1024;
+/// The background writer flushes at least this often while busy.
+const MAX_BATCH: usize = 1024;
+
 thread_local! {
-    static FILE_MAP: RefCell<HashMap<OsString, File>> = RefCell::new(HashMap::new());
+    // Moved onto the writer thread while it is running.
+    static LOGS: RefCell<Logs> = RefCell::new(Logs::default());
+    static WRITER: RefCell<Option<Writer>> = const { RefCell::new(None) };
 }
 
-byond_fn!(fn log_write(path, data, ...rest) {
-    FILE_MAP.with(|cell| -> Result<()> {
-        // open file
-        let mut map = cell.borrow_mut();
-        let path = Path::new(path as &str);
-        let file = match map.entry(path.into()) {
+/// Every open log, and the options for each path.
+#[derive(Default)]
+struct Logs {
+    files: HashMap<OsString, LogFile>,
+    configs: HashMap<OsString, LogConfig>,
+}
+
+impl Logs {
+    /// Appends `entry` to the log at `path`, opening it if needed. Nothing is
+    /// flushed until `flush`.
+    fn write(&mut self, path: &Path, at: DateTime<Utc>, entry: LogEntry) -> Result<()> {
+        let file = match self.files.entry(path.into()) {
             Entry::Occupied(elem) => elem.into_mut(),
-            Entry::Vacant(elem) => elem.insert(open(path)?),
+            Entry::Vacant(elem) => {
+                let config = self.configs.get(path.as_os_str()).cloned();
+                elem.insert(LogFile::open(path, config.unwrap_or_default())?)
+            }
+        };
+        let line = file.config.format(at, entry);
+        file.write(line.as_bytes())
+    }
+
+    /// Applies `changes` to the options for `path`, or goes back to the
+    /// defaults if there are none.
+    fn configure(&mut self, path: OsString, changes: Option<Map<String, Value>>) -> Result<()> {
+        let config = match changes {
+            Some(changes) => self
+                .configs
+                .get(&path)
+                .cloned()
+                .unwrap_or_default()
+                .update(changes)?,
+            None => LogConfig::default(),
+        };
+        if let Some(file) = self.files.get_mut(&path) {
+            file.config = config.clone();
+        }
+        self.configs.insert(path, config);
+        Ok(())
+    }
+
+    /// Flushes every open log, returning the first failure.
+    fn flush(&mut self) -> Result<()> {
+        self.files
+            .values_mut()
+            .map(LogFile::flush)
+            .fold(Ok(()), Result::and)
+    }
+
+    fn close_all(&mut self) -> Result<()> {
+        let flushed = self.flush();
+        self.files.clear();
+        flushed
+    }
+}
+
+/// Per-path options set by `log_configure`.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+struct LogConfig {
+    /// Rotate before a write would take the file past this many bytes.
+    max_bytes: Option<u64>,
+    /// Rotate once the file was started this many seconds ago.
+    max_age_seconds: Option<u64>,
+    /// How many rotated files to keep, the oldest are deleted.
+    max_backups: usize,
+    naming: Naming,
+    /// chrono format string for timestamps. JSON logs default to RFC 3339.
+    timestamp_format: Option<String>,
+    timezone: Timezone,
+    /// Add the time since the log was configured to every entry.
+    #[serde(deserialize_with = "deserialize_byond_bool")]
+    round_time: bool,
+    /// When `round_time` was last set, which it counts from.
+    #[serde(skip)]
+    configured_at: DateTime<Utc>,
+    /// Everything set so far, which later changes are merged into.
+    #[serde(skip)]
+    options: Map<String, Value>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            max_bytes: None,
+            max_age_seconds: None,
+            max_backups: 5,
+            naming: Naming::default(),
+            timestamp_format: None,
+            timezone: Timezone::default(),
+            round_time: false,
+            configured_at: Utc::now(),
+            options: Map::new(),
+        }
+    }
+}
+
+impl LogConfig {
+    /// Merges `changes` into the options set so far, a null value going back
+    /// to the default. `round_time` restarts only when it is among `changes`.
+    fn update(&self, changes: Map<String, Value>) -> Result<Self> {
+        let restart_round = changes.contains_key("round_time");
+        let mut options = self.options.clone();
+        for (key, value) in changes {
+            if value.is_null() {
+                options.remove(&key);
+            } else {
+                options.insert(key, value);
+            }
+        }
+        let mut config = Self::deserialize(Value::Object(options.clone()))?;
+        config.options = options;
+        if !restart_round {
+            config.configured_at = self.configured_at;
+        }
+        if let Some(format) = &config.timestamp_format {
+            // chrono only notices bad formats when it panics printing them.
+            if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+                return Err(Error::InvalidTimestampFormat(format.clone()));
+            }
+        }
+        Ok(config)
+    }
+
+    /// Turns an entry written at `at` into the line(s) to append to the log.
+    fn format(&self, at: DateTime<Utc>, entry: LogEntry) -> String {
+        match entry {
+            LogEntry::Raw(data) => data,
+            LogEntry::Text(data) => {
+                let mut prefix = format!("[{}]", self.timestamp(at, false));
+                if let Some(round_time) = self.round_time(at) {
+                    prefix.push_str(&format!(" [{round_time}]"));
+                }
+                // first line timestamped, remaining lines continued
+                let mut entry = String::with_capacity(data.len() + 32);
+                let mut iter = data.split('\n');
+                if let Some(line) = iter.next() {
+                    entry.push_str(&format!("{prefix} {line}\n"));
+                }
+                for line in iter {
+                    entry.push_str(&format!(" - {line}\n"));
+                }
+                entry
+            }
+            LogEntry::Json { category, fields } => {
+                let mut entry = Map::new();
+                entry.insert("timestamp".to_owned(), self.timestamp(at, true).into());
+                entry.insert("category".to_owned(), category.into());
+                if let Some(round_time) = self.round_time(at) {
+                    entry.insert("round_time".to_owned(), round_time.into());
+                }
+                entry.extend(fields);
+                let mut line = Value::Object(entry).to_string();
+                line.push('\n');
+                line
+            }
+        }
+    }
+
+    fn timestamp(&self, at: DateTime<Utc>, json: bool) -> String {
+        let format = self.timestamp_format.as_deref();
+        match self.timezone {
+            Timezone::Utc => format_timestamp(at, format, json),
+            Timezone::Local => format_timestamp(at.with_timezone(&Local), format, json),
+            Timezone::Fixed(offset) => format_timestamp(at.with_timezone(&offset), format, json),
+        }
+    }
+
+    /// `HH:MM:SS.mmm` since the log was configured, if enabled.
+    fn round_time(&self, at: DateTime<Utc>) -> Option<String> {
+        if !self.round_time {
+            return None;
+        }
+        let millis = (at - self.configured_at).num_milliseconds().max(0);
+        let seconds = millis / 1000;
+        Some(format!(
+            "{:02}:{:02}:{:02}.{:03}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60,
+            millis % 1000
+        ))
+    }
+}
+
+fn format_timestamp<Tz: TimeZone>(at: DateTime<Tz>, format: Option<&str>, json: bool) -> String
+where
+    Tz::Offset: std::fmt::Display,
+{
+    match format {
+        Some(format) => at.format(format).to_string(),
+        None if json => at.to_rfc3339_opts(SecondsFormat::Millis, true),
+        None => at.format(DEFAULT_TIMESTAMP_FORMAT).to_string(),
+    }
+}
+
+/// Which clock timestamps are in: "utc", "local", or a fixed offset such as
+/// "+02:00".
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(try_from = "String")]
+enum Timezone {
+    #[default]
+    Utc,
+    Local,
+    Fixed(FixedOffset),
+}
+
+impl TryFrom<String> for Timezone {
+    type Error = String;
+
+    fn try_from(timezone: String) -> std::result::Result<Self, String> {
+        match timezone.as_str() {
+            "utc" | "UTC" => Ok(Self::Utc),
+            "local" => Ok(Self::Local),
+            offset => offset
+                .parse()
+                .map(Self::Fixed)
+                .map_err(|_| format!("unknown timezone {offset:?}")),
+        }
+    }
+}
+
+/// What is being logged, formatted once the log's options are known.
+enum LogEntry {
+    /// Written as is.
+    Raw(String),
+    /// Timestamped, with every line after the first marked as continuing it.
+    Text(String),
+    /// One line of a JSON lines log.
+    Json {
+        category: String,
+        fields: Map<String, Value>,
+    },
+}
+
+/// What rotated files are called.
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Naming {
+    /// `runtime.log.1` is the newest, older files are shifted up by one.
+    #[default]
+    Numbered,
+    /// `runtime.log.20240101-120000000`, named for when it was rotated.
+    Timestamp,
+}
+
+struct LogFile {
+    path: PathBuf,
+    // Only `None` between closing the file to rotate it and reopening it.
+    file: Option<BufWriter<File>>,
+    /// Bytes written since the last rotation, or attempt at one.
+    size: u64,
+    /// When the last rotation, or attempt at one, happened.
+    started: SystemTime,
+    config: LogConfig,
+}
+
+impl LogFile {
+    fn open(path: &Path, config: LogConfig) -> Result<Self> {
+        let file = open(path)?;
+        let metadata = file.metadata()?;
+        Ok(Self {
+            path: path.to_owned(),
+            file: Some(BufWriter::new(file)),
+            size: metadata.len(),
+            // Not every platform records creation times, so the age of a
+            // log from before a restart may count from now instead.
+            started: metadata.created().unwrap_or_else(|_| SystemTime::now()),
+            config,
+        })
+    }
+
+    /// Appends `data`, rotating the file first if it is due. If rotating
+    /// fails, `data` still goes to the current file and the failure is
+    /// returned. Rotation isn't tried again until it is next due, so a file
+    /// which stays locked is reported once per rotation rather than per write.
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        let mut rotated = Ok(());
+        if self.due_for_rotation(data.len() as u64) {
+            rotated = self.rotate();
+            self.size = 0;
+            self.started = SystemTime::now();
+        }
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => self.file.insert(BufWriter::new(open(&self.path)?)),
         };
+        file.write_all(data)?;
+        self.size += data.len() as u64;
+        rotated
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if let Some(file) = &mut self.file {
+            file.flush()?;
+        }
+        Ok(())
+    }
+
+    fn due_for_rotation(&self, incoming: u64) -> bool {
+        if self.size == 0 {
+            return false;
+        }
+        let too_big = self
+            .config
+            .max_bytes
+            .is_some_and(|max| self.size + incoming > max);
+        let too_old = self.config.max_age_seconds.is_some_and(|max| {
+            self.started
+                .elapsed()
+                .is_ok_and(|age| age >= Duration::from_secs(max))
+        });
+        too_big || too_old
+    }
 
-        if rest.first().map(|x| &**x) == Some("false") {
-            // Write the data to the file with no accoutrements.
-            write!(file, "{data}")?;
+    fn rotate(&mut self) -> Result<()> {
+        // Windows can't rename a file which is still open.
+        if let Some(mut file) = self.file.take() {
+            file.flush()?;
+        }
+        let keep = self.config.max_backups;
+        if keep == 0 {
+            fs::remove_file(&self.path)?;
         } else {
-            // write first line, timestamped
-            let mut iter = data.split('\n');
-            if let Some(line) = iter.next() {
-                writeln!(file, "[{}] {}", Utc::now().format("%F %T%.3f"), line)?;
+            match self.config.naming {
+                Naming::Numbered => rotate_numbered(&self.path, keep)?,
+                Naming::Timestamp => rotate_timestamped(&self.path, keep)?,
             }
+        }
+        Ok(())
+    }
+}
+
+fn with_suffix(path: &Path, suffix: impl std::fmt::Display) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{suffix}"));
+    name.into()
+}
 
-            // write remaining lines
-            for line in iter {
-                writeln!(file, " - {line}")?;
+fn rotate_numbered(path: &Path, keep: usize) -> Result<()> {
+    // Only the backups which exist need shifting up, the last one of them
+    // replacing whatever is in the way.
+    let existing = (1..keep)
+        .take_while(|n| with_suffix(path, n).exists())
+        .count();
+    for n in (1..=existing).rev() {
+        fs::rename(with_suffix(path, n), with_suffix(path, n + 1))?;
+    }
+    fs::rename(path, with_suffix(path, 1))?;
+    Ok(())
+}
+
+fn rotate_timestamped(path: &Path, keep: usize) -> Result<()> {
+    let timestamp = Utc::now().format(ROTATED_TIMESTAMP_FORMAT);
+    fs::rename(path, with_suffix(path, timestamp))?;
+
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return Ok(());
+    };
+    let prefix = format!("{name}.");
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut rotated: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .and_then(|file_name| file_name.strip_prefix(&prefix))
+                .is_some_and(|suffix| {
+                    suffix.len() == ROTATED_TIMESTAMP_LEN
+                        && suffix.bytes().all(|b| b.is_ascii_digit() || b == b'-')
+                })
+        })
+        .map(|entry| entry.path())
+        .collect();
+    rotated.sort();
+    let excess = rotated.len().saturating_sub(keep);
+    for old in &rotated[..excess] {
+        fs::remove_file(old)?;
+    }
+    Ok(())
+}
+
+/// The background writer thread, which owns `Logs` while it runs.
+struct Writer {
+    commands: SyncSender<Command>,
+    thread: JoinHandle<Logs>,
+}
+
+enum Command {
+    Write(PathBuf, DateTime<Utc>, LogEntry),
+    /// Changes to the options for a path, or `None` to reset them.
+    Configure(OsString, Option<Map<String, Value>>),
+    /// Flushes every log, then replies with any failures since the last reply.
+    Flush(SyncSender<Option<String>>),
+    /// Like `Flush`, but also closes every log.
+    CloseAll(SyncSender<Option<String>>),
+}
+
+/// Failed background writes, kept until `log_flush` reports them.
+#[derive(Default)]
+struct Failures {
+    count: usize,
+    last: Option<Error>,
+}
+
+impl Failures {
+    fn record(&mut self, result: Result<()>) {
+        if let Err(error) = result {
+            self.count += 1;
+            self.last = Some(error);
+        }
+    }
+
+    fn take(&mut self) -> Option<String> {
+        let count = std::mem::take(&mut self.count);
+        let last = self.last.take()?;
+        Some(format!(
+            "{count} log write(s) failed, the last with: {last}"
+        ))
+    }
+}
+
+fn run_writer(mut logs: Logs, commands: Receiver<Command>) -> Logs {
+    let mut failures = Failures::default();
+    while let Ok(first) = commands.recv() {
+        // Write out whatever has queued up, then flush once for the lot.
+        let batch = std::iter::once(first).chain(commands.try_iter().take(MAX_BATCH - 1));
+        for command in batch {
+            match command {
+                Command::Write(path, at, entry) => failures.record(logs.write(&path, at, entry)),
+                Command::Configure(path, changes) => failures.record(logs.configure(path, changes)),
+                Command::Flush(reply) => {
+                    failures.record(logs.flush());
+                    let _ = reply.send(failures.take());
+                }
+                Command::CloseAll(reply) => {
+                    failures.record(logs.close_all());
+                    let _ = reply.send(failures.take());
+                }
             }
         }
+        failures.record(logs.flush());
+    }
+    logs
+}
 
+/// Hands `command` to the writer thread if there is one, or runs it now.
+fn submit(command: Command) -> Result<()> {
+    WRITER.with_borrow(|writer| match writer {
+        Some(writer) => writer
+            .commands
+            .send(command)
+            .map_err(|_| Error::LogWriterStopped),
+        None => LOGS.with_borrow_mut(|logs| match command {
+            Command::Write(path, at, entry) => {
+                logs.write(&path, at, entry)?;
+                logs.flush()
+            }
+            Command::Configure(path, changes) => logs.configure(path, changes),
+            Command::Flush(_) => logs.flush(),
+            Command::CloseAll(_) => logs.close_all(),
+        }),
+    })
+}
+
+/// Waits until everything written so far has been handed to the operating
+/// system, closing every log too
+/// if `close` is set. Returns any failures of the writer thread since the last
+/// time this was called.
+fn flush(close: bool) -> Result<Option<String>> {
+    let (reply, replied) = sync_channel(1);
+    let command = if close {
+        Command::CloseAll(reply)
+    } else {
+        Command::Flush(reply)
+    };
+    let background = WRITER.with_borrow(Option::is_some);
+    submit(command)?;
+    if !background {
+        return Ok(None);
+    }
+    replied.recv().map_err(|_| Error::LogWriterStopped)
+}
+
+/// Starts or stops the writer thread, moving the open logs over to it or
+/// back again. Stopping waits for everything queued to be written.
+fn set_background(enabled: bool, queue_size: usize) -> Result<()> {
+    WRITER.with_borrow_mut(|writer| {
+        if let Some(Writer { commands, thread }) = writer.take() {
+            drop(commands);
+            // A panicked writer loses the logs' options, but not their contents.
+            LOGS.set(thread.join().unwrap_or_default());
+        }
+        if !enabled {
+            return Ok(());
+        }
+        let logs = LOGS.take();
+        let (commands, received) = sync_channel(queue_size);
+        let thread = thread::Builder::new()
+            .name("rustg-log-writer".to_owned())
+            .spawn(move || run_writer(logs, received))?;
+        *writer = Some(Writer { commands, thread });
         Ok(())
-    }).err()
+    })
+}
+
+byond_fn!(fn log_write(path, data, ...rest) {
+    let entry = if rest.first().map(|x| &**x) == Some("false") {
+        // Write the data to the file with no accoutrements.
+        LogEntry::Raw(data.to_owned())
+    } else {
+        LogEntry::Text(data.to_owned())
+    };
+    submit(Command::Write(path.into(), Utc::now(), entry))
+        .inspect_err(report_error)
+        .err()
+});
+
+byond_fn!(fn log_write_json(path, category, fields) {
+    json_entry(category, fields)
+        .and_then(|entry| submit(Command::Write(path.into(), Utc::now(), entry)))
+        .inspect_err(report_error)
+        .err()
+});
+
+byond_fn!(fn log_configure(path, options) {
+    configure(path, options).inspect_err(report_error).err()
+});
+
+byond_fn!(fn log_set_background(enabled, queue_size) {
+    let queue_size = if queue_size.is_empty() {
+        Ok(DEFAULT_QUEUE_SIZE)
+    } else {
+        queue_size.parse().map_err(Error::from)
+    };
+    queue_size
+        .and_then(|queue_size| set_background(enabled == "1", queue_size))
+        .inspect_err(report_error)
+        .err()
 });
 
+byond_fn!(
+    fn log_flush() {
+        flush(false).unwrap_or_else(|error| {
+            report_error(&error);
+            Some(error.to_string())
+        })
+    }
+);
+
 byond_fn!(
     fn log_close_all() {
-        FILE_MAP.with(|cell| {
-            let mut map = cell.borrow_mut();
-            map.clear();
-        });
+        let _ = flush(true).inspect_err(report_error);
         Some("")
     }
 );
 
+/// Checks the fields of a JSON lines entry, which are written after the
+/// timestamp and category.
+fn json_entry(category: &str, fields: &str) -> Result<LogEntry> {
+    let fields: Map<String, Value> = if fields.is_empty() {
+        Map::new()
+    } else {
+        serde_json::from_str(fields)?
+    };
+    if let Some(reserved) = RESERVED_JSON_FIELDS
+        .into_iter()
+        .find(|key| fields.contains_key(*key))
+    {
+        return Err(Error::ReservedLogField(reserved.to_owned()));
+    }
+    Ok(LogEntry::Json {
+        category: category.to_owned(),
+        fields,
+    })
+}
+
+/// Changes the options for `path`, taking effect immediately if the log is
+/// already open. Empty options go back to the defaults.
+fn configure(path: &str, options: &str) -> Result<()> {
+    let changes = if options.is_empty() {
+        None
+    } else {
+        let changes: Map<String, Value> = serde_json::from_str(options)?;
+        // Checked here too, as the background writer can't report mistakes.
+        LogConfig::default().update(changes.clone())?;
+        Some(changes)
+    };
+    submit(Command::Configure(path.into(), changes))
+}
+
 fn open(path: &Path) -> Result<File> {
+    check_path(path)?;
     if let Some(parent) = path.parent() {
         fs::create_dir_all(parent)?;
     }
 
     Ok(OpenOptions::new().append(true).create(true).open(path)?)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::TrySendError;
+
+    #[test]
+    fn json_entries_are_one_line() {
+        let entry = json_entry("attack", r#"{"message": "first\nsecond", "damage": 5}"#).unwrap();
+        let entry = LogConfig::default().format(Utc::now(), entry);
+        assert_eq!(entry.matches('\n').count(), 1);
+        let entry: Value = serde_json::from_str(&entry).unwrap();
+        assert_eq!(entry["category"], "attack");
+        assert_eq!(entry["message"], "first\nsecond");
+        assert_eq!(entry["damage"], 5);
+        let keys: Vec<&String> = entry.as_object().unwrap().keys().collect();
+        assert_eq!(keys[..2], ["timestamp", "category"]);
+
+        assert!(json_entry("attack", "").is_ok());
+        assert!(json_entry("attack", "[1, 2]").is_err());
+        assert!(json_entry("attack", r#"{"category": "x"}"#).is_err());
+    }
+
+    #[test]
+    fn timestamps_follow_options() {
+        let at = DateTime::parse_from_rfc3339("2024-01-01T12:00:00.250Z")
+            .unwrap()
+            .to_utc();
+        let text = || LogEntry::Text("first\nsecond".to_owned());
+
+        let config = LogConfig::default();
+        assert_eq!(
+            config.format(at, text()),
+            "[2024-01-01 12:00:00.250] first\n - second\n"
+        );
+
+        let mut config = LogConfig::default()
+            .update(options(
+                r#"{"timestamp_format": "%H:%M:%S %z", "timezone": "+02:00", "round_time": 1}"#,
+            ))
+            .unwrap();
+        config.configured_at = at - chrono::Duration::milliseconds(3_723_004);
+        assert_eq!(
+            config.format(at, text()),
+            "[14:00:00 +0200] [01:02:03.004] first\n - second\n"
+        );
+
+        let config = LogConfig::default();
+        assert!(
+            config
+                .update(options(r#"{"timestamp_format": "%Q"}"#))
+                .is_err()
+        );
+        assert!(config.update(options(r#"{"timezone": "mars"}"#)).is_err());
+    }
+
+    fn options(json: &str) -> Map<String, Value> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn configuring_keeps_options_not_mentioned() {
+        let path = OsString::from("merged.log");
+        let mut logs = Logs::default();
+        let started = Utc::now() - chrono::Duration::hours(1);
+        logs.configure(
+            path.clone(),
+            Some(options(r#"{"max_bytes": 100, "round_time": 1}"#)),
+        )
+        .unwrap();
+        logs.configs.get_mut(&path).unwrap().configured_at = started;
+
+        logs.configure(path.clone(), Some(options(r#"{"timezone": "local"}"#)))
+            .unwrap();
+        let config = &logs.configs[&path];
+        assert_eq!(config.max_bytes, Some(100));
+        assert!(config.round_time);
+        assert!(matches!(config.timezone, Timezone::Local));
+        assert_eq!(config.configured_at, started);
+
+        logs.configure(
+            path.clone(),
+            Some(options(r#"{"max_bytes": null, "round_time": 1}"#)),
+        )
+        .unwrap();
+        let config = &logs.configs[&path];
+        assert_eq!(config.max_bytes, None);
+        assert!(matches!(config.timezone, Timezone::Local));
+        assert!(config.configured_at > started);
+
+        logs.configure(path.clone(), None).unwrap();
+        assert!(matches!(logs.configs[&path].timezone, Timezone::Utc));
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("rustg-log-test-{}", std::process::id()))
+            .join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn rotating_log(path: &Path, max_backups: usize) -> LogFile {
+        let config = LogConfig {
+            max_bytes: Some(4),
+            max_backups,
+            ..Default::default()
+        };
+        LogFile::open(path, config).unwrap()
+    }
+
+    fn queue_raw(path: &Path, data: &str) {
+        submit(Command::Write(
+            path.to_owned(),
+            Utc::now(),
+            LogEntry::Raw(data.to_owned()),
+        ))
+        .unwrap();
+    }
+
+    #[test]
+    fn background_writes_keep_their_order_and_are_written_on_close() {
+        let path = temp_dir("background").join("game.log");
+        set_background(true, 4).unwrap();
+        let lines: Vec<String> = (0..100).map(|n| format!("{n}\n")).collect();
+        for line in &lines {
+            queue_raw(&path, line);
+        }
+        assert_eq!(flush(true).unwrap(), None);
+        assert_eq!(fs::read_to_string(&path).unwrap(), lines.concat());
+        set_background(false, 0).unwrap();
+    }
+
+    #[test]
+    fn background_writes_wait_for_a_full_queue() {
+        let path = temp_dir("full_queue").join("game.log");
+        set_background(true, 1).unwrap();
+        // The writer stalls replying to this until it is received.
+        let (reply, replied) = sync_channel(0);
+        submit(Command::Flush(reply)).unwrap();
+        queue_raw(&path, "first\n");
+        WRITER.with_borrow(|writer| {
+            let command = Command::Write(
+                path.clone(),
+                Utc::now(),
+                LogEntry::Raw("second\n".to_owned()),
+            );
+            let sent = writer.as_ref().unwrap().commands.try_send(command);
+            assert!(matches!(sent, Err(TrySendError::Full(_))));
+        });
+        assert_eq!(replied.recv().unwrap(), None);
+        queue_raw(&path, "second\n");
+        flush(true).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first\nsecond\n");
+        set_background(false, 0).unwrap();
+    }
+
+    #[test]
+    fn numbered_rotation_shifts_existing_backups() {
+        let path = temp_dir("numbered").join("game.log");
+        let mut log = rotating_log(&path, 3);
+        for line in ["1111", "2222", "3333", "4444", "5555"] {
+            log.write(line.as_bytes()).unwrap();
+        }
+        log.flush().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "5555");
+        assert_eq!(fs::read_to_string(with_suffix(&path, 1)).unwrap(), "4444");
+        assert_eq!(fs::read_to_string(with_suffix(&path, 3)).unwrap(), "2222");
+        assert!(!with_suffix(&path, 4).exists());
+    }
+
+    #[test]
+    fn failed_rotation_keeps_appending() {
+        let path = temp_dir("locked").join("game.log");
+        // A directory in the way of the backup makes the rename fail.
+        fs::create_dir_all(with_suffix(&path, 1).join("in_the_way")).unwrap();
+        let mut log = rotating_log(&path, 1);
+        log.write(b"11").unwrap();
+        log.write(b"22").unwrap();
+        assert!(log.write(b"33").is_err());
+        // Not due again until another max_bytes have been written.
+        log.write(b"44").unwrap();
+        assert!(log.write(b"55").is_err());
+        log.flush().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "1122334455");
+    }
+}
@@ -0,0 +1,236 @@
+//! Per-export call metrics, recorded by `byond_fn!` when DM turns them on.
+//!
+//! Exports are only ever called from the BYOND thread, so everything here is
+//! thread-local and costs a single flag check while metrics are disabled.
+
+use crate::{
+    byond::{byond_bool, report_error},
+    error::{Error, Result},
+};
+use serde_json::{Value, json};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+/// How many slow calls are kept for `metrics_dump`, oldest dropped first.
+const SLOW_CALL_HISTORY: usize = 64;
+
+thread_local! {
+    static ENABLED: Cell<bool> = const { Cell::new(false) };
+    static METRICS: RefCell<Metrics> = RefCell::new(Metrics::default());
+}
+
+#[derive(Default)]
+struct Metrics {
+    exports: BTreeMap<&'static str, ExportStats>,
+    slow_threshold: Option<Duration>,
+    slow_calls: VecDeque<SlowCall>,
+}
+
+#[derive(Default)]
+struct ExportStats {
+    calls: u64,
+    total: Duration,
+    max: Duration,
+    arg_bytes: u64,
+    max_arg_bytes: usize,
+}
+
+struct SlowCall {
+    name: &'static str,
+    elapsed: Duration,
+    arg_bytes: usize,
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Runs the export `name`, recording how long it took if metrics are enabled.
+pub fn measure<R>(name: &'static str, arg_bytes: usize, f: impl FnOnce() -> R) -> R {
+    if !ENABLED.with(Cell::get) {
+        return f();
+    }
+
+    let started = Instant::now();
+    let result = f();
+    record(name, arg_bytes, started.elapsed());
+    result
+}
+
+fn record(name: &'static str, arg_bytes: usize, elapsed: Duration) {
+    METRICS.with(|metrics| {
+        let mut metrics = metrics.borrow_mut();
+        let stats = metrics.exports.entry(name).or_default();
+        stats.calls += 1;
+        stats.total += elapsed;
+        stats.max = stats.max.max(elapsed);
+        stats.arg_bytes += arg_bytes as u64;
+        stats.max_arg_bytes = stats.max_arg_bytes.max(arg_bytes);
+
+        if metrics.slow_threshold.is_some_and(|limit| elapsed >= limit) {
+            if metrics.slow_calls.len() == SLOW_CALL_HISTORY {
+                metrics.slow_calls.pop_front();
+            }
+            metrics.slow_calls.push_back(SlowCall {
+                name,
+                elapsed,
+                arg_bytes,
+            });
+        }
+    })
+}
+
+fn dump() -> String {
+    METRICS.with(|metrics| {
+        let metrics = metrics.borrow();
+        let exports: serde_json::Map<String, Value> = metrics
+            .exports
+            .iter()
+            .map(|(name, stats)| {
+                let stats = json!({
+                    "calls": stats.calls,
+                    "total_ms": millis(stats.total),
+                    "mean_ms": millis(stats.total) / stats.calls as f64,
+                    "max_ms": millis(stats.max),
+                    "arg_bytes": stats.arg_bytes,
+                    "max_arg_bytes": stats.max_arg_bytes,
+                });
+                (name.to_string(), stats)
+            })
+            .collect();
+        let slow_calls: Vec<Value> = metrics
+            .slow_calls
+            .iter()
+            .map(|call| {
+                json!({
+                    "name": call.name,
+                    "ms": millis(call.elapsed),
+                    "arg_bytes": call.arg_bytes,
+                })
+            })
+            .collect();
+        json!({
+            "enabled": ENABLED.with(Cell::get),
+            "slow_call_ms": metrics.slow_threshold.map(millis),
+            "exports": exports,
+            "slow_calls": slow_calls,
+        })
+        .to_string()
+    })
+}
+
+fn reset() {
+    METRICS.with(|metrics| {
+        let mut metrics = metrics.borrow_mut();
+        metrics.exports.clear();
+        metrics.slow_calls.clear();
+    })
+}
+
+/// Options are `{"enabled": 1, "slow_call_ms": 50}`, both optional.
+/// A `slow_call_ms` of zero or null stops tracing slow calls.
+fn configure(options: &str) -> Result<()> {
+    let options: Value = serde_json::from_str(options)?;
+    let enabled = match options.get("enabled") {
+        Some(enabled) => Some(byond_bool(enabled).ok_or_else(|| {
+            Error::InvalidMetricsOption(format!("enabled must be TRUE or FALSE, not {enabled}"))
+        })?),
+        None => None,
+    };
+    let threshold = match options.get("slow_call_ms") {
+        Some(slow_call_ms) => Some(slow_threshold(slow_call_ms)?),
+        None => None,
+    };
+    // Only change anything once every option is known to be good.
+    if let Some(enabled) = enabled {
+        ENABLED.with(|cell| cell.set(enabled));
+    }
+    if let Some(threshold) = threshold {
+        METRICS.with(|metrics| metrics.borrow_mut().slow_threshold = threshold);
+    }
+    Ok(())
+}
+
+fn slow_threshold(slow_call_ms: &Value) -> Result<Option<Duration>> {
+    let invalid = || Error::InvalidMetricsOption(format!("slow_call_ms of {slow_call_ms}"));
+    match slow_call_ms {
+        Value::Null => Ok(None),
+        Value::Number(number) => {
+            let ms = number.as_f64().ok_or_else(invalid)?;
+            if ms == 0.0 {
+                return Ok(None);
+            }
+            Duration::try_from_secs_f64(ms / 1000.0)
+                .map(Some)
+                .map_err(|_| invalid())
+        }
+        _ => Err(invalid()),
+    }
+}
+
+byond_fn!(fn metrics_configure(options) {
+    configure(options).inspect_err(report_error).err()
+});
+
+byond_fn!(
+    fn metrics_dump() {
+        Some(dump())
+    }
+);
+
+byond_fn!(
+    fn metrics_reset() {
+        reset();
+        Some("")
+    }
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_only_when_enabled() {
+        measure("disabled_export", 3, || ());
+        assert!(!dump().contains("disabled_export"));
+
+        configure(r#"{"enabled": true, "slow_call_ms": 1}"#).unwrap();
+        measure("enabled_export", 3, || ());
+        measure("enabled_export", 5, || {
+            std::thread::sleep(Duration::from_millis(2))
+        });
+
+        let metrics: Value = serde_json::from_str(&dump()).unwrap();
+        let stats = &metrics["exports"]["enabled_export"];
+        assert_eq!(stats["calls"], 2);
+        assert_eq!(stats["arg_bytes"], 8);
+        assert_eq!(stats["max_arg_bytes"], 5);
+        assert!(stats["max_ms"].as_f64().unwrap() >= 2.0);
+        assert_eq!(metrics["slow_calls"][0]["name"], "enabled_export");
+        assert_eq!(metrics["slow_calls"][0]["arg_bytes"], 5);
+
+        reset();
+        let metrics: Value = serde_json::from_str(&dump()).unwrap();
+        assert_eq!(metrics["exports"], json!({}));
+        assert_eq!(metrics["enabled"], true);
+    }
+
+    #[test]
+    fn options_are_checked_before_anything_changes() {
+        // json_encode(list("enabled" = TRUE)) from DM.
+        configure(r#"{"enabled": 1}"#).unwrap();
+        assert!(ENABLED.with(Cell::get));
+        configure(r#"{"enabled": 0, "slow_call_ms": 1e300}"#).unwrap_err();
+        configure(r#"{"enabled": 0, "slow_call_ms": -5}"#).unwrap_err();
+        configure(r#"{"enabled": "no"}"#).unwrap_err();
+        assert!(ENABLED.with(Cell::get));
+
+        configure(r#"{"enabled": 0, "slow_call_ms": 20}"#).unwrap();
+        assert!(!ENABLED.with(Cell::get));
+        let metrics: Value = serde_json::from_str(&dump()).unwrap();
+        assert_eq!(metrics["slow_call_ms"], 20.0);
+    }
+}
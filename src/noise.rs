@@ -4,14 +4,14 @@ use std::{
     collections::hash_map::{Entry, HashMap},
 };
 
-use crate::error::Result;
+use crate::{byond::report_error, error::Result};
 
 thread_local! {
     static GENERATORS: RefCell<HashMap<String,  Perlin>> = RefCell::new(HashMap::new());
 }
 
 byond_fn!(fn noise_get_at_coordinates(seed, x, y) {
-    get_at_coordinates(seed, x, y).ok()
+    get_at_coordinates(seed, x, y).inspect_err(report_error).ok()
 });
 
 //note that this will be 0 at integer x & y, scaling is left up to the caller
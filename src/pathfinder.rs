@@ -1,3 +1,4 @@
+use crate::byond::report_failure;
 use num_integer::sqrt;
 use pathfinding::prelude::astar;
 use serde::{Deserialize, Serialize};
@@ -70,7 +71,10 @@ enum RegisteringNodesError {
 
 byond_fn!(fn register_nodes_astar(json) {
     match register_nodes(json) { Ok(s) => Some(s),
-        Err(e) => Some(format!("{e}"))
+        Err(e) => {
+            report_failure("pathfinder", &e);
+            Some(format!("{e}"))
+        }
     }
 });
 
@@ -96,7 +100,10 @@ fn register_nodes(json: &str) -> Result<String, RegisteringNodesError> {
 byond_fn!(fn add_node_astar(json) {
     match add_node(json) {
         Ok(s) => Some(s),
-        Err(e) => Some(format!("{e}"))
+        Err(e) => {
+            report_failure("pathfinder", &e);
+            Some(format!("{e}"))
+        }
     }
 });
 
@@ -138,7 +145,10 @@ enum DeleteNodeError {
 byond_fn!(fn remove_node_astar(unique_id) {
     match remove_node(unique_id) {
         Ok(s) => Some(s),
-        Err(e) => Some(format!("{e}"))
+        Err(e) => {
+            report_failure("pathfinder", &e);
+            Some(format!("{e}"))
+        }
     }
 });
 
@@ -187,12 +197,19 @@ byond_fn!(fn generate_path_astar(start_node_id, goal_node_id) {
         match generate_path(start_node_id, goal_node_id) {
             Ok(vector) => Some(match serde_json::to_string(&vector) {
                 Ok(s) => s,
-                Err(_) => "Cannot serialize path".to_string(),
+                Err(e) => {
+                    report_failure("json", e);
+                    "Cannot serialize path".to_string()
+                }
             }),
-            Err(e) => Some(format!("{e}"))
+            Err(e) => {
+                report_failure("pathfinder", &e);
+                Some(format!("{e}"))
+            }
         }
     }
     else {
+        report_failure("invalid_argument", "Invalid arguments");
         Some("Invalid arguments".to_string())
     }
 });
@@ -1,9 +1,9 @@
-use crate::error::Result;
+use crate::{byond::report_error, error::Result};
 use fast_poisson::Poisson2D;
 use std::collections::HashSet;
 
 byond_fn!(fn noise_poisson_map(seed, width, length, radius) {
-    get_poisson_map(seed, width, length, radius).ok()
+    get_poisson_map(seed, width, length, radius).inspect_err(report_error).ok()
 });
 
 fn get_poisson_map(
@@ -1,3 +1,4 @@
+use crate::byond::report_failure;
 use redis::{Client, Commands, RedisError};
 use std::cell::RefCell;
 use std::collections::HashMap;
@@ -155,7 +156,7 @@ fn get_messages() -> String {
 }
 
 byond_fn!(fn redis_connect(addr) {
-    connect(addr).err().map(|e| e.to_string())
+    connect(addr).err().map(|e| reported(e.to_string()))
 });
 
 byond_fn!(
@@ -166,7 +167,7 @@ byond_fn!(
 );
 
 byond_fn!(fn redis_subscribe(channel) {
-    subscribe(channel)
+    subscribe(channel).map(reported)
 });
 
 byond_fn!(
@@ -176,5 +177,11 @@ byond_fn!(
 );
 
 byond_fn!(fn redis_publish(channel, message) {
-    publish(channel, message)
+    publish(channel, message).map(reported)
 });
+
+/// Reports an error message for the result envelope, passing it through.
+fn reported(error: String) -> String {
+    report_failure("redis", &error);
+    error
+}
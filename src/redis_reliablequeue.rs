@@ -1,3 +1,4 @@
+use crate::byond::report_failure;
 use redis::{Client, Commands, RedisError};
 use std::cell::RefCell;
 use std::num::NonZeroUsize;
@@ -140,9 +141,9 @@ fn lpop(key: &str, count: Option<NonZeroUsize>) -> serde_json::Value {
 byond_fn!(fn redis_connect_rq(addr) {
     match connect(addr) {
         Ok(_) => Some(serde_json::json!({"success": true, "content": ""}).to_string()),
-        Err(e) => Some(serde_json::json!({
+        Err(e) => Some(reported(serde_json::json!({
             "success": false, "content": format!("Failed to connect to {addr}: {e}")
-        }).to_string()),
+        }))),
     }
 });
 
@@ -155,15 +156,15 @@ byond_fn!(
 
 byond_fn!(fn redis_lpush(key, elements) {
     match serde_json::from_str(elements) {
-        Ok(elem) => Some(lpush(key, elem).to_string()),
-        Err(e) => Some(serde_json::json!({
+        Ok(elem) => Some(reported(lpush(key, elem))),
+        Err(e) => Some(reported(serde_json::json!({
             "success": false, "content": format!("Failed to deserialize JSON: {e}")
-        }).to_string()),
+        }))),
     }
 });
 
 byond_fn!(fn redis_lrange(key, start, stop) {
-    Some(lrange(key, start.parse().unwrap_or(0), stop.parse().unwrap_or(-1)).to_string())
+    Some(reported(lrange(key, start.parse().unwrap_or(0), stop.parse().unwrap_or(-1))))
 });
 
 byond_fn!(fn redis_lpop(key, count) {
@@ -172,5 +173,14 @@ byond_fn!(fn redis_lpop(key, count) {
     } else {
         count.parse().unwrap_or(0)
     };
-    Some(lpop(key, std::num::NonZeroUsize::new(count_parsed)).to_string())
+    Some(reported(lpop(key, std::num::NonZeroUsize::new(count_parsed))))
 });
+
+/// Serializes a `{"success", "content"}` result, reporting it for the result
+/// envelope if it failed.
+fn reported(result: serde_json::Value) -> String {
+    if result["success"] == false {
+        report_failure("redis", result["content"].as_str().unwrap_or_default());
+    }
+    result.to_string()
+}
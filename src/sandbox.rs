@@ -0,0 +1,95 @@
+//! Optional confinement of every path rust_g touches to a set of root
+//! directories. Until DM sets the roots, any path is allowed.
+
+use crate::error::{Error, Result};
+use std::{
+    io,
+    path::{Component, Path, PathBuf},
+    sync::OnceLock,
+};
+
+static ALLOWED_ROOTS: OnceLock<Vec<PathBuf>> = OnceLock::new();
+
+byond_fn!(fn fs_set_allowed_roots(roots) {
+    set_allowed_roots(roots).err()
+});
+
+/// Sets the allowed roots, once. Later calls fail, so nothing can widen the
+/// sandbox after startup.
+fn set_allowed_roots(roots: &str) -> Result<()> {
+    let roots: Vec<String> = serde_json::from_str(roots)?;
+    let roots = roots
+        .iter()
+        .map(std::fs::canonicalize)
+        .collect::<io::Result<Vec<_>>>()?;
+    ALLOWED_ROOTS
+        .set(roots)
+        .map_err(|_| Error::SandboxAlreadySet)
+}
+
+/// Fails with `Error::PathNotAllowed` unless `path` resolves to somewhere
+/// inside one of the allowed roots. Paths which don't exist yet are resolved
+/// from their closest existing ancestor.
+pub fn check_path(path: impl AsRef<Path>) -> Result<()> {
+    let Some(roots) = ALLOWED_ROOTS.get() else {
+        return Ok(());
+    };
+    let path = path.as_ref();
+    match resolve(path) {
+        Some(resolved) if roots.iter().any(|root| resolved.starts_with(root)) => Ok(()),
+        _ => Err(Error::PathNotAllowed(path.display().to_string())),
+    }
+}
+
+fn resolve(path: &Path) -> Option<PathBuf> {
+    let path = if path.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        path
+    };
+    let mut existing = path;
+    let mut missing = Vec::new();
+    loop {
+        match existing.canonicalize() {
+            Ok(mut resolved) => {
+                for component in missing.into_iter().rev() {
+                    match component {
+                        Component::Normal(name) => resolved.push(name),
+                        Component::CurDir => {}
+                        // Can't tell where `..` leads below a directory which
+                        // doesn't exist, so don't allow it at all.
+                        _ => return None,
+                    }
+                }
+                return Some(resolved);
+            }
+            Err(_) => {
+                missing.push(existing.components().next_back()?);
+                existing = existing.parent()?;
+                if existing.as_os_str().is_empty() {
+                    existing = Path::new(".");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_missing_paths_from_existing_ancestor() {
+        let cwd = std::env::current_dir().unwrap().canonicalize().unwrap();
+        assert_eq!(resolve(Path::new("")), Some(cwd.clone()));
+        assert_eq!(
+            resolve(Path::new("no_such_dir/file.txt")),
+            Some(cwd.join("no_such_dir").join("file.txt"))
+        );
+        assert_eq!(resolve(Path::new("no_such_dir/../../etc")), None);
+        assert_eq!(
+            resolve(Path::new("src/../Cargo.toml")),
+            Some(cwd.join("Cargo.toml"))
+        );
+    }
+}
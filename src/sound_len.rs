@@ -1,4 +1,7 @@
-use crate::error::{Error::SoundLen, Result};
+use crate::{
+    byond::report_error,
+    error::{Error::SoundLen, Result},
+};
 use std::{collections::HashMap, fs::File, time::Duration};
 use symphonia::{
     self,
@@ -15,7 +18,10 @@ use symphonia::{
 byond_fn!(fn sound_len(sound_path) {
     match get_sound_length(sound_path) {
         Ok(r) => return Some(r),
-        Err(e) => return Some(e.to_string())
+        Err(e) => {
+            report_error(&e);
+            return Some(e.to_string())
+        }
     }
 });
 
@@ -1,7 +1,11 @@
-use crate::jobs;
+use crate::{
+    byond::report_failure,
+    jobs::{self, JobKind},
+};
+use base64::{Engine, prelude::BASE64_STANDARD};
 use dashmap::DashMap;
 use mysql::{
-    OptsBuilder, Params, Pool, PoolConstraints, PoolOpts,
+    Conn, Opts, OptsBuilder, Params, Pool, PoolConstraints, PoolOpts,
     consts::{ColumnFlags, ColumnType::*},
     prelude::Queryable,
 };
@@ -18,6 +22,8 @@ const DEFAULT_PORT: u16 = 3306;
 // The `mysql` crate defaults to 10 and 100 for these, but that is too large.
 const DEFAULT_MIN_THREADS: usize = 1;
 const DEFAULT_MAX_THREADS: usize = 10;
+/// How long cancelling a query may spend connecting and sending `KILL QUERY`.
+const KILL_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[derive(Deserialize)]
 struct ConnectOptions {
@@ -35,27 +41,36 @@ struct ConnectOptions {
 byond_fn!(fn sql_connect_pool(options) {
     let options = match serde_json::from_str::<ConnectOptions>(options) {
         Ok(options) => options,
-        Err(e) => return Some(err_to_json(e)),
+        Err(e) => return Some(report_err_to_json(e)),
     };
     Some(match sql_connect(options) {
         Ok(o) => o.to_string(),
-        Err(e) => err_to_json(e)
+        Err(e) => report_err_to_json(e)
     })
 });
 
-byond_fn!(fn sql_query_blocking(handle, query, params) {
-    Some(match do_query(handle, query, params) {
+byond_fn!(fn sql_query_blocking(handle, query, params, encoding) {
+    Some(match do_query(handle, query, params, encoding) {
         Ok(o) => o.to_string(),
-        Err(e) => err_to_json(e)
+        Err(e) => report_err_to_json(e)
     })
 });
 
-byond_fn!(fn sql_query_async(handle, query, params) {
+byond_fn!(fn sql_query_async(handle, query, params, timeout, encoding) {
+    let timeout = match timeout.parse::<f32>() {
+        _ if timeout.is_empty() => None,
+        Ok(seconds) => match jobs::timeout_from_secs(seconds) {
+            Ok(timeout) => timeout,
+            Err(e) => return Some(report_err_to_json(e)),
+        },
+        Err(e) => return Some(report_err_to_json(e)),
+    };
     let handle = handle.to_owned();
     let query = query.to_owned();
     let params = params.to_owned();
-    Some(jobs::start(move || {
-        match do_query(&handle, &query, &params) {
+    let encoding = encoding.to_owned();
+    Some(jobs::start_with_timeout(JobKind::Sql, timeout, move || {
+        match do_query(&handle, &query, &params, &encoding) {
             Ok(o) => o.to_string(),
             Err(e) => err_to_json(e)
         }
@@ -66,7 +81,7 @@ byond_fn!(fn sql_query_async(handle, query, params) {
 byond_fn!(fn sql_disconnect_pool(handle) {
     let handle = match handle.parse::<usize>() {
         Ok(o) => o,
-        Err(e) => return Some(err_to_json(e)),
+        Err(e) => return Some(report_err_to_json(e)),
     };
     Some(
          match POOL.remove(&handle) {
@@ -85,7 +100,7 @@ byond_fn!(fn sql_disconnect_pool(handle) {
 byond_fn!(fn sql_connected(handle) {
     let handle = match handle.parse::<usize>() {
         Ok(o) => o,
-        Err(e) => return Some(err_to_json(e)),
+        Err(e) => return Some(report_err_to_json(e)),
     };
     Some(
         match POOL.get(&handle) {
@@ -106,9 +121,16 @@ byond_fn!(fn sql_check_query(id) {
 // ----------------------------------------------------------------------------
 // Main connect and query implementation
 
-static POOL: Lazy<DashMap<usize, Pool>> = Lazy::new(DashMap::new);
+static POOL: Lazy<DashMap<usize, Database>> = Lazy::new(DashMap::new);
 static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
 
+/// A connection pool, and the options it was made with for connecting
+/// outside of it.
+struct Database {
+    pool: Pool,
+    opts: Opts,
+}
+
 fn sql_connect(options: ConnectOptions) -> Result<serde_json::Value, Box<dyn Error>> {
     let pool_constraints = PoolConstraints::new(
         options.min_threads.unwrap_or(DEFAULT_MIN_THREADS),
@@ -134,24 +156,42 @@ fn sql_connect(options: ConnectOptions) -> Result<serde_json::Value, Box<dyn Err
         .write_timeout(options.write_timeout.map(Duration::from_secs_f32))
         .pool_opts(pool_opts);
 
-    let pool = Pool::new(builder)?;
+    let opts = Opts::from(builder);
+    let pool = Pool::new(opts.clone())?;
 
     let handle = NEXT_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-    POOL.insert(handle, pool);
+    POOL.insert(handle, Database { pool, opts });
     Ok(json!({
         "status": "ok",
         "handle": handle.to_string(),
     }))
 }
 
-fn do_query(handle: &str, query: &str, params: &str) -> Result<serde_json::Value, Box<dyn Error>> {
+/// Runs `query`, returning every row. Binary BLOB columns come back as an
+/// array of byte values, or as a base64 string with the base64 encoding.
+fn do_query(
+    handle: &str,
+    query: &str,
+    params: &str,
+    encoding: &str,
+) -> Result<serde_json::Value, Box<dyn Error>> {
+    let token = jobs::current();
+    let handle: usize = handle.parse()?;
     let mut conn = {
-        let pool = match POOL.get(&handle.parse()?) {
+        let database = match POOL.get(&handle) {
             Some(s) => s,
             None => return Ok(json!({"status": "offline"})),
         };
-        pool.get_conn()?
+        database.pool.get_conn()?
     };
+    if token.is_cancelled() {
+        return Err(Box::new(crate::error::Error::JobCancelled));
+    }
+
+    // If the job is abandoned mid-query, have the server kill it so the
+    // connection is released instead of waiting on the result.
+    let connection_id = conn.connection_id();
+    let kill_guard = token.on_cancel(move || kill_query(handle, connection_id));
 
     let query_result = conn.exec_iter(query, params_from_json(params))?;
     let affected = query_result.affected_rows();
@@ -166,6 +206,9 @@ fn do_query(handle: &str, query: &str, params: &str) -> Result<serde_json::Value
 
     let mut rows: Vec<serde_json::Value> = Vec::new();
     for row in query_result {
+        if token.is_cancelled() {
+            return Err(Box::new(crate::error::Error::JobCancelled));
+        }
         let row = row?;
         let mut json_row: Vec<serde_json::Value> = Vec::new();
         for (i, col) in row.columns_ref().iter().enumerate() {
@@ -182,14 +225,16 @@ fn do_query(handle: &str, query: &str, params: &str) -> Result<serde_json::Value
                     | MYSQL_TYPE_LONG_BLOB
                     | MYSQL_TYPE_MEDIUM_BLOB
                     | MYSQL_TYPE_TINY_BLOB => {
-                        if col.flags().contains(ColumnFlags::BINARY_FLAG) {
+                        if !col.flags().contains(ColumnFlags::BINARY_FLAG) {
+                            serde_json::Value::String(String::from_utf8_lossy(b).into_owned())
+                        } else if encoding == crate::byond::BINARY_BASE64 {
+                            serde_json::Value::String(BASE64_STANDARD.encode(b))
+                        } else {
                             serde_json::Value::Array(
                                 b.iter()
                                     .map(|x| serde_json::Value::Number(Number::from(*x)))
                                     .collect(),
                             )
-                        } else {
-                            serde_json::Value::String(String::from_utf8_lossy(b).into_owned())
                         }
                     }
                     _ => serde_json::Value::Null,
@@ -214,6 +259,7 @@ fn do_query(handle: &str, query: &str, params: &str) -> Result<serde_json::Value
         rows.push(serde_json::Value::Array(json_row));
     }
 
+    drop(kill_guard);
     drop(conn);
 
     Ok(json! {{
@@ -225,9 +271,32 @@ fn do_query(handle: &str, query: &str, params: &str) -> Result<serde_json::Value
     }})
 }
 
+/// Kills the query running on `connection_id`. This uses a connection of its
+/// own rather than one from the pool, which may be full of the very queries
+/// that need killing.
+fn kill_query(handle: usize, connection_id: u32) {
+    let Some(opts) = POOL.get(&handle).map(|database| database.opts.clone()) else {
+        return;
+    };
+    let opts = OptsBuilder::from_opts(opts)
+        .tcp_connect_timeout(Some(KILL_TIMEOUT))
+        .read_timeout(Some(KILL_TIMEOUT))
+        .write_timeout(Some(KILL_TIMEOUT));
+    if let Ok(mut conn) = Conn::new(opts) {
+        let _ = conn.query_drop(format!("KILL QUERY {connection_id}"));
+    }
+}
+
 // ----------------------------------------------------------------------------
 // Helpers
 
+/// Like `err_to_json`, also reporting the error for the result envelope. Only
+/// for errors of the export itself, not of the query jobs it starts.
+fn report_err_to_json<E: std::fmt::Display>(e: E) -> String {
+    report_failure("sql", &e);
+    err_to_json(e)
+}
+
 fn err_to_json<E: std::fmt::Display>(e: E) -> String {
     json!({
         "status": "err",
@@ -1,3 +1,4 @@
+use crate::byond::report_failure;
 use chrono::{FixedOffset, Local, Utc};
 use std::{
     cell::RefCell,
@@ -59,8 +60,18 @@ fn format_timestamp(format: &str, offset: &str) -> Option<String> {
     if offset.is_empty() {
         Some(Local::now().format(format).to_string())
     } else {
-        let offset_seconds = offset.parse::<i32>().ok()? * 3600;
-        let timezone = FixedOffset::east_opt(offset_seconds)?;
+        let offset_seconds = offset
+            .parse::<i32>()
+            .inspect_err(|error| report_failure("parse", error))
+            .ok()?
+            * 3600;
+        let Some(timezone) = FixedOffset::east_opt(offset_seconds) else {
+            report_failure(
+                "invalid_argument",
+                format!("Offset of {offset} hours is out of range"),
+            );
+            return None;
+        };
         Some(
             Utc::now()
                 .with_timezone(&timezone)
@@ -1,4 +1,4 @@
-use crate::error::Result;
+use crate::{byond::report_error, error::Result};
 
 byond_fn!(fn toml_file_to_json(path) {
     serde_json::to_string(
@@ -6,9 +6,12 @@ byond_fn!(fn toml_file_to_json(path) {
             Ok(value) => serde_json::json!({
                 "success": true, "content": value
             }),
-            Err(error) => serde_json::json!({
-                "success": false, "content": error.to_string()
-            }),
+            Err(error) => {
+                report_error(&error);
+                serde_json::json!({
+                    "success": false, "content": error.to_string()
+                })
+            }
         }
     ).ok()
 });
@@ -28,13 +31,27 @@ byond_fn!(fn toml_encode(value) {
                 "success": true, "content": value
             }),
 
-            Err(error) => serde_json::json!({
-                "success": false, "content": error.to_string()
-            }),
+            Err(error) => {
+                report_error(&error);
+                serde_json::json!({
+                    "success": false, "content": error.to_string()
+                })
+            }
         }
     ).ok()
 });
 
+// Like toml_encode, but saves the result to `path` with an atomic write.
+#[cfg(feature = "file")]
+byond_fn!(fn toml_encode_to_file(value, path, backup) {
+    Some(crate::byond::result_json(
+        toml_encode_impl(value)
+            .and_then(|toml| crate::file::write_atomic(toml.as_bytes(), path, backup == "1").map(|_| toml))
+            .map(serde_json::Value::String)
+            .inspect_err(report_error),
+    ))
+});
+
 fn toml_encode_impl(value: &str) -> Result<String> {
     Ok(toml_dep::to_string_pretty(&serde_json::from_str::<
         toml_dep::Value,
@@ -1,6 +1,11 @@
-use crate::{error::Result, http::HTTP_CLIENT, jobs};
+use crate::{
+    error::{Error, Result},
+    http::{HTTP_CLIENT, content_length},
+    jobs::{self, CancelToken, CancellableReader, JobKind, ProgressHandle, ProgressReader},
+    sandbox::check_path,
+};
 use std::fs;
-use std::io::Write;
+use std::io::{Read, Write};
 use std::path::Path;
 use zip::ZipArchive;
 
@@ -21,25 +26,63 @@ fn construct_unzip(url: &str, unzip_directory: &str) -> UnzipPrep {
 
 byond_fn!(fn unzip_download_async(url, unzip_directory) {
     let unzip = construct_unzip(url, unzip_directory);
-    Some(jobs::start(move ||
+    Some(jobs::start(JobKind::Unzip, move ||
         do_unzip_download(unzip).unwrap_or_else(|e| e.to_string())
     ))
 });
 
 fn do_unzip_download(prep: UnzipPrep) -> Result<String> {
+    let token = jobs::current();
     let unzip_path = Path::new(&prep.unzip_directory);
-    let response = prep.req.send_bytes(&[]).map_err(Box::new)?;
+    check_path(unzip_path)?;
+    let req = match token.remaining() {
+        Some(timeout) => prep.req.timeout(timeout),
+        None => prep.req,
+    };
+    let response = req.send_bytes(&[]).map_err(Box::new)?;
 
+    let progress = jobs::progress();
+    progress.begin("bytes", content_length(&response));
     let mut content = Vec::new();
-    response.into_reader().read_to_end(&mut content)?;
+    ProgressReader::new(
+        CancellableReader::new(response.into_reader(), token.clone()),
+        progress.clone(),
+    )
+    .read_to_end(&mut content)?;
 
+    extract(content, unzip_path, &token, &progress)?;
+    Ok("true".to_string())
+}
+
+/// Writes every file of the zip archive `content` into `unzip_path`.
+fn extract(
+    content: Vec<u8>,
+    unzip_path: &Path,
+    token: &CancelToken,
+    progress: &ProgressHandle,
+) -> Result<()> {
     let reader = std::io::Cursor::new(content);
     let mut archive = ZipArchive::new(reader)?;
+    progress.begin("files", Some(archive.len() as u64));
 
     for i in 0..archive.len() {
+        if token.is_cancelled() {
+            return Err(Error::JobCancelled);
+        }
         let mut entry = archive.by_index(i)?;
 
-        let file_path = unzip_path.join(entry.name());
+        // Entry names can be absolute or climb out of the directory with `..`,
+        // so those are refused outright. The sandbox check on top also catches
+        // symlinks out of the allowed roots.
+        let Some(name) = entry.enclosed_name() else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Zip entry {} is outside the directory", entry.name()),
+            )
+            .into());
+        };
+        let file_path = unzip_path.join(name);
+        check_path(&file_path)?;
 
         if let Some(parent) = file_path.parent() {
             fs::create_dir_all(parent)?
@@ -54,11 +97,53 @@ fn do_unzip_download(prep: UnzipPrep) -> Result<String> {
         let mut writer = std::io::BufWriter::new(file);
         std::io::copy(&mut entry, &mut writer)?;
         writer.flush()?;
+        progress.advance(1);
     }
 
-    Ok("true".to_string())
+    Ok(())
 }
 
 byond_fn!(fn unzip_check(id) {
     Some(jobs::check(id))
 });
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zip::{ZipWriter, write::SimpleFileOptions};
+
+    fn archive(names: &[&str]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for name in names {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(name.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn entries_outside_the_directory_are_refused() {
+        let root = std::env::temp_dir().join(format!("rustg-unzip-test-{}", std::process::id()));
+        let dir = root.join("out");
+        let _ = fs::remove_dir_all(&root);
+        let unzip = |names: &[&str]| {
+            extract(
+                archive(names),
+                &dir,
+                &CancelToken::default(),
+                &ProgressHandle::default(),
+            )
+        };
+
+        unzip(&["a.txt", "sub/b.txt"]).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("sub/b.txt")).unwrap(),
+            "sub/b.txt"
+        );
+
+        assert!(unzip(&["../escaped.txt"]).is_err());
+        assert!(!root.join("escaped.txt").exists());
+    }
+}
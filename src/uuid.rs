@@ -1,3 +1,4 @@
+use crate::byond::report_failure;
 use uuid::Uuid;
 
 byond_fn!(
@@ -20,7 +21,10 @@ byond_fn!(
 
 byond_fn!(
     fn cuid2_len(length) {
-        let length = length.parse::<u16>().ok()?;
+        let length = length
+            .parse::<u16>()
+            .inspect_err(|error| report_failure("parse", error))
+            .ok()?;
         Some(
             cuid2::CuidConstructor::new()
                 .with_length(length)
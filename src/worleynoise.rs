@@ -1,4 +1,4 @@
-use crate::error::Result;
+use crate::{byond::report_error, error::Result};
 use core::panic;
 use rand::{
     RngExt,
@@ -11,7 +11,7 @@ use std::{
 };
 
 byond_fn!(fn worley_generate(region_size, threshold, node_per_region_chance, size, node_min, node_max) {
-    worley_noise(region_size, threshold, node_per_region_chance, size, node_min, node_max).ok()
+    worley_noise(region_size, threshold, node_per_region_chance, size, node_min, node_max).inspect_err(report_error).ok()
 });
 
 const RANGE: usize = 4;